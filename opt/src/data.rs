@@ -3,7 +3,7 @@ use rand::{
     seq::SliceRandom,
 };
 use rand_distr::Poisson;
-use std::{sync::Arc, time::Instant};
+use std::{sync::Arc, time::{Instant, SystemTime}};
 
 use crate::phys::{Acceleration, Position, Velocity, Distance};
 
@@ -11,14 +11,29 @@ use crate::phys::{Acceleration, Position, Velocity, Distance};
 pub enum Type {
     Album,
     User,
+    Artist,
+    Label,
 }
 
-#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
-pub struct AlbumId(pub u64);
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ItemKind {
+    Album,
+    Track,
+}
 
-#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+/// Bandcamp numbers albums and tracks independently, so the kind is part of the key
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct AlbumId(pub ItemKind, pub u64);
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct UserId(pub u64);
 
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ArtistId(pub u64);
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct LabelId(pub u64);
+
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct EntityId(u32);
 
@@ -35,21 +50,40 @@ pub struct Entity {
     pub acceleration: Acceleration,
     pub dragged: Option<Drag>,
     pub is_under_mouse: bool,
-    pub is_scraped: bool,
+    pub state: ScrapeState,
+    /// When the pages the entity was last scraped from were fetched
+    pub scraped_at: Option<SystemTime>,
     pub data: Arc<EntityData>,
     pub related: im::HashSet<EntityId>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EntityData {
     Album(Album),
     User(User),
+    Artist(Artist),
+    Label(Label),
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum RelationshipKind {
+    /// `from` is an album in the collection of the user `to`
+    Purchase,
+    /// `from` is an album released by the artist `to`
+    Release,
+    /// `from` is an artist on the roster of the label `to`
+    Roster,
+    /// `from` is an album whose page recommends the album `to`
+    Recommendation,
+    /// `from` is a user following the artist or label `to`
+    Follow,
 }
 
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct Relationship {
-    pub album: EntityId,
-    pub user: EntityId,
+    pub kind: RelationshipKind,
+    pub from: EntityId,
+    pub to: EntityId,
 }
 
 #[derive(Default, Debug)]
@@ -71,6 +105,18 @@ pub struct Data {
     pub relationships: im::HashSet<Relationship>,
     pub albums: im::HashMap<AlbumId, EntityId>,
     pub users: im::HashMap<UserId, EntityId>,
+    pub artists: im::HashMap<ArtistId, EntityId>,
+    pub labels: im::HashMap<LabelId, EntityId>,
+    /// Every url an entity has been known by, the one it was first added with and its canonical one once scraped
+    pub urls: im::HashMap<String, EntityId>,
+    /// When each purchase was added to the user's collection, for those scraped from the collection itself
+    pub added: im::HashMap<Relationship, SystemTime>,
+    /// Descriptions of albums that were seen in collections, which may never have been scraped themselves
+    pub album_info: im::HashMap<AlbumId, AlbumInfo>,
+    /// Relationships that first appeared while re-scraping an entity, with when they were found
+    pub changed: im::HashMap<Relationship, SystemTime>,
+    /// Only set within [`Data::refreshed`]
+    recording_changes: bool,
 }
 
 impl Clone for Data {
@@ -80,6 +126,13 @@ impl Clone for Data {
             relationships: self.relationships.clone(),
             albums: self.albums.clone(),
             users: self.users.clone(),
+            artists: self.artists.clone(),
+            labels: self.labels.clone(),
+            urls: self.urls.clone(),
+            added: self.added.clone(),
+            album_info: self.album_info.clone(),
+            changed: self.changed.clone(),
+            recording_changes: false,
         }
     }
 
@@ -88,21 +141,138 @@ impl Clone for Data {
         self.relationships.clone_from(&source.relationships);
         self.albums.clone_from(&source.albums);
         self.users.clone_from(&source.users);
+        self.artists.clone_from(&source.artists);
+        self.labels.clone_from(&source.labels);
+        self.urls.clone_from(&source.urls);
+        self.added.clone_from(&source.added);
+        self.album_info.clone_from(&source.album_info);
+        self.changed.clone_from(&source.changed);
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct User {
     pub id: UserId,
     pub url: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Album {
     pub id: AlbumId,
     pub url: String,
 }
 
+/// An album in a user's collection, as listed on their fan page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Purchase {
+    pub album: Album,
+    pub added: SystemTime,
+    pub info: AlbumInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumInfo {
+    pub title: String,
+    pub artist: String,
+    /// Bandcamp's number for the top level genre, which has no published list of names
+    pub genre_id: Option<u32>,
+    /// The track played when the release is opened from a collection, by id and title
+    pub featured_track: Option<(u64, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Artist {
+    pub id: ArtistId,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Label {
+    pub id: LabelId,
+    pub url: String,
+}
+
+/// Where a paginated crawl left off
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Cursor {
+    Fans { album: Album, token: String },
+    /// Followed bands are paged through after the collection, from `following` if there are more
+    Collection { user: User, token: String, following: Option<String> },
+    Following { user: User, token: String },
+}
+
+impl Cursor {
+    /// The entity whose relations are being paged through
+    pub fn entity(&self) -> EntityData {
+        match self {
+            Cursor::Fans { album, .. } => album.clone().into(),
+            Cursor::Collection { user, .. } | Cursor::Following { user, .. } => user.clone().into(),
+        }
+    }
+}
+
+/// How far along scraping an entity is
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ScrapeState {
+    #[default]
+    Unscraped,
+    /// Waiting in the background queue
+    Queued,
+    /// Being scraped right now
+    InProgress,
+    /// Scraped until a budget ran out, it can be continued from the cursor
+    Partial(Cursor),
+    Done,
+    /// The last attempt failed, with the reason and where to carry on from if it was continuing a partial scrape
+    Failed { error: String, cursor: Option<Cursor> },
+}
+
+impl ScrapeState {
+    /// Whether the entity's own page has been scraped, even if not all of its relations have
+    pub fn is_scraped(&self) -> bool {
+        matches!(self, ScrapeState::Partial(_) | ScrapeState::Done)
+    }
+}
+
+trait Node: Clone + Into<EntityData> {
+    type Id: Copy + Eq + core::hash::Hash;
+
+    fn id(&self) -> Self::Id;
+    fn index(data: &Data) -> &im::HashMap<Self::Id, EntityId>;
+    fn index_mut(data: &mut Data) -> &mut im::HashMap<Self::Id, EntityId>;
+}
+
+macro_rules! node {
+    ($ty:ident, $id:ident, $field:ident) => {
+        impl From<$ty> for EntityData {
+            fn from(node: $ty) -> Self {
+                EntityData::$ty(node)
+            }
+        }
+
+        impl Node for $ty {
+            type Id = $id;
+
+            fn id(&self) -> $id {
+                self.id
+            }
+
+            fn index(data: &Data) -> &im::HashMap<$id, EntityId> {
+                &data.$field
+            }
+
+            fn index_mut(data: &mut Data) -> &mut im::HashMap<$id, EntityId> {
+                &mut data.$field
+            }
+        }
+    };
+}
+
+node!(Album, AlbumId, albums);
+node!(User, UserId, users);
+node!(Artist, ArtistId, artists);
+node!(Label, LabelId, labels);
+
 impl EntityData {
     fn at_random_location(self) -> Entity {
         let mut rng = rand::thread_rng();
@@ -115,7 +285,8 @@ impl EntityData {
             acceleration: Acceleration::default(),
             dragged: None,
             is_under_mouse: false,
-            is_scraped: false,
+            state: ScrapeState::Unscraped,
+            scraped_at: None,
             data: Arc::new(self),
             related: im::HashSet::new(),
         }
@@ -132,7 +303,8 @@ impl EntityData {
             acceleration: Acceleration::default(),
             dragged: None,
             is_under_mouse: false,
-            is_scraped: false,
+            state: ScrapeState::Unscraped,
+            scraped_at: None,
             data: Arc::new(self),
             related: im::HashSet::new(),
         }
@@ -209,45 +381,189 @@ impl<'a> core::iter::IntoIterator for &'a Entities {
     }
 }
 
+impl EntityData {
+    pub fn url(&self) -> &str {
+        match self {
+            EntityData::Album(Album { url, .. })
+            | EntityData::User(User { url, .. })
+            | EntityData::Artist(Artist { url, .. })
+            | EntityData::Label(Label { url, .. }) => url,
+        }
+    }
+}
+
 impl Data {
+    fn get<N: Node>(&self, node: &N) -> Option<EntityId> {
+        N::index(self).get(&node.id()).copied()
+    }
+
+    fn insert<N: Node>(&mut self, node: &N, near: Option<Position>) -> EntityId {
+        let data: EntityData = node.clone().into();
+        // A followed label was added as an artist, it turns into the label once that's known
+        if let EntityData::Label(label) = &data {
+            if let Some(id) = self.artists.remove(&ArtistId(label.id.0)) {
+                self.entities[id].data = Arc::new(data.clone());
+                self.labels.insert(label.id, id);
+                self.urls.insert(label.url.clone(), id);
+                return id;
+            }
+        }
+        let url = data.url().to_owned();
+        let entity = match near {
+            Some(position) => data.at_random_location_near(position),
+            None => data.at_random_location(),
+        };
+        let id = self.entities.add(entity);
+        N::index_mut(self).insert(node.id(), id);
+        self.urls.insert(url, id);
+        id
+    }
+
+    fn link<A: Node, B: Node>(&mut self, kind: RelationshipKind, from: &A, to: &B) -> Relationship {
+        let (from, to) = match (self.get(from), self.get(to)) {
+            (Some(from), Some(to)) => (from, to),
+            (Some(from), None) => {
+                let position = self.entities[from].position;
+                (from, self.insert(to, Some(position)))
+            }
+            (None, Some(to)) => {
+                let position = self.entities[to].position;
+                (self.insert(from, Some(position)), to)
+            }
+            (None, None) => (self.insert(from, None), self.insert(to, None)),
+        };
+
+        let relationship = Relationship { kind, from, to };
+        if self.relationships.insert(relationship.clone()).is_none() && self.recording_changes {
+            self.changed.insert(relationship.clone(), SystemTime::now());
+        }
+        self.entities[from].related.insert(to);
+        self.entities[to].related.insert(from);
+        relationship
+    }
+
+    /// Adds relationships found re-scraping an entity, recording the ones that weren't there before in `changed`
+    pub fn refreshed(&mut self, f: impl FnOnce(&mut Self)) {
+        self.recording_changes = true;
+        f(self);
+        self.recording_changes = false;
+    }
+
+    /// Marks the entity as scraped at `at`, replacing its data with the scraped copy which carries the canonical url
+    pub fn set_scraped(&mut self, data: EntityData, at: SystemTime) {
+        self.finish_scrape(data, ScrapeState::Done, at);
+    }
+
+    /// Like [`Data::set_scraped`] for an entity whose relations were only scraped up to the cursor
+    pub fn set_partial(&mut self, data: EntityData, cursor: Cursor, at: SystemTime) {
+        self.finish_scrape(data, ScrapeState::Partial(cursor), at);
+    }
+
+    fn finish_scrape(&mut self, data: EntityData, state: ScrapeState, at: SystemTime) {
+        // An entity can be scraped without anything linking to it yet, like an artist on its own
+        let id = self.add_node(&data);
+        self.urls.insert(data.url().to_owned(), id);
+        self.entities[id].data = Arc::new(data);
+        self.entities[id].state = state;
+        self.entities[id].scraped_at = Some(at);
+    }
+
+    /// The scrape state of the entity with the given url, if it is in the graph
+    pub fn state(&self, url: &str) -> Option<&ScrapeState> {
+        self.urls.get(url).map(|&id| &self.entities[id].state)
+    }
+
+    fn state_mut(&mut self, url: &str) -> Option<&mut ScrapeState> {
+        let id = *self.urls.get(url)?;
+        Some(&mut self.entities[id].state)
+    }
+
+    /// A request for the entity is waiting in the background queue, unless it is already scraped or being scraped
+    ///
+    /// Failed continuations keep their cursor until they start, in case they are cancelled before then.
+    pub fn queued(&mut self, url: &str) {
+        if let Some(state @ (ScrapeState::Unscraped | ScrapeState::Failed { cursor: None, .. })) = self.state_mut(url) {
+            *state = ScrapeState::Queued;
+        }
+    }
+
+    /// The queued request for the entity was cancelled before it started
+    pub fn unqueued(&mut self, url: &str) {
+        if let Some(state @ ScrapeState::Queued) = self.state_mut(url) {
+            *state = ScrapeState::Unscraped;
+        }
+    }
+
+    pub fn started(&mut self, url: &str) {
+        if let Some(state) = self.state_mut(url) {
+            *state = ScrapeState::InProgress;
+        }
+    }
+
+    /// The request scraping the entity failed, if it was continuing from `cursor` it can be continued from there again
+    pub fn failed(&mut self, url: &str, error: String, cursor: Option<Cursor>) {
+        if let Some(state) = self.state_mut(url) {
+            *state = ScrapeState::Failed { error, cursor };
+        }
+    }
+
+    /// The request scraping the entity ended, if it was cancelled without any results it is back to how it was
+    pub fn stopped(&mut self, url: &str) {
+        let Some(&id) = self.urls.get(url) else { return };
+        let entity = &mut self.entities[id];
+        if entity.state == ScrapeState::InProgress {
+            entity.state = if entity.scraped_at.is_some() { ScrapeState::Done } else { ScrapeState::Unscraped };
+        }
+    }
+
+    /// Adds an entity on its own, unless it is already in the graph
+    pub fn add_node(&mut self, data: &EntityData) -> EntityId {
+        fn get_or_insert<N: Node>(this: &mut Data, node: &N) -> EntityId {
+            this.get(node).unwrap_or_else(|| this.insert(node, None))
+        }
+        match data {
+            EntityData::Album(album) => get_or_insert(self, album),
+            EntityData::User(user) => get_or_insert(self, user),
+            EntityData::Artist(artist) => get_or_insert(self, artist),
+            EntityData::Label(label) => get_or_insert(self, label),
+        }
+    }
+
     pub fn add_relationship(&mut self, album: &Album, user: &User) {
-        let (album, user) = if let Some(&album) = self.albums.get(&album.id) {
-            let &mut user = self.users
-                .entry(user.id)
-                .or_insert_with(|| {
-                    self.entities.add(EntityData::User(user.clone()).at_random_location_near(self.entities[album].position))
-                });
-            (album, user)
-        } else if let Some(&user) = self.users.get(&user.id) {
-            let &mut album = self.albums
-                .entry(album.id)
-                .or_insert_with(|| {
-                    self.entities.add(EntityData::Album(album.clone()).at_random_location_near(self.entities[user].position))
-                });
-            (album, user)
-        } else {
-            let &mut album = self.albums
-                .entry(album.id)
-                .or_insert_with(|| {
-                    self.entities.add(EntityData::Album(album.clone()).at_random_location())
-                });
-            let &mut user = self.users
-                .entry(user.id)
-                .or_insert_with(|| {
-                    self.entities.add(EntityData::User(user.clone()).at_random_location())
-                });
-            (album, user)
+        self.link(RelationshipKind::Purchase, album, user);
+    }
+
+    pub fn add_purchase(&mut self, purchase: &Purchase, user: &User) {
+        let relationship = self.link(RelationshipKind::Purchase, &purchase.album, user);
+        self.added.insert(relationship, purchase.added);
+        self.album_info.insert(purchase.album.id, purchase.info.clone());
+    }
+
+    pub fn add_release(&mut self, album: &Album, artist: &Artist) {
+        self.link(RelationshipKind::Release, album, artist);
+    }
+
+    pub fn add_roster(&mut self, artist: &Artist, label: &Label) {
+        self.link(RelationshipKind::Roster, artist, label);
+    }
+
+    /// Followed bands can't be told apart from artists, so those already known as labels are followed as labels
+    pub fn add_follow(&mut self, user: &User, artist: &Artist) {
+        let label = Label { id: LabelId(artist.id.0), url: artist.url.clone() };
+        match self.get(&label) {
+            Some(_) => self.link(RelationshipKind::Follow, user, &label),
+            None => self.link(RelationshipKind::Follow, user, artist),
         };
+    }
 
-        self.relationships.insert(Relationship { album, user });
-        self.entities[album].related.insert(user);
-        self.entities[user].related.insert(album);
+    pub fn add_recommendation(&mut self, album: &Album, recommended: &Album) {
+        self.link(RelationshipKind::Recommendation, album, recommended);
     }
 
     pub fn spawn_random(&mut self, albums: u64, users: u64) {
         let mut rng = rand::thread_rng();
 
-        let mut albums = Vec::from_iter((0..albums).map(|_| { let id = rand::random(); Album { id: AlbumId(id), url: format!("no://random/album/{id}") } }));
+        let mut albums = Vec::from_iter((0..albums).map(|_| { let id = rand::random(); Album { id: AlbumId(ItemKind::Album, id), url: format!("no://random/album/{id}") } }));
         let users = Vec::from_iter((0..users).map(|_| { let id = rand::random(); User { id: UserId(id), url: format!("no://random/user/{id}") } }));
 
         let mut linked_albums = Vec::new();
@@ -273,3 +589,6 @@ impl Data {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
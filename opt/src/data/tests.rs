@@ -0,0 +1,138 @@
+use std::time::{Duration, SystemTime};
+
+use super::{Album, AlbumId, Artist, ArtistId, Cursor, Data, EntityData, ItemKind, Label, LabelId, ScrapeState, User, UserId};
+
+fn album(id: u64) -> Album {
+    Album { id: AlbumId(ItemKind::Album, id), url: format!("https://artist.bandcamp.com/album/{id}") }
+}
+
+fn fan(id: u64) -> User {
+    User { id: UserId(id), url: format!("https://bandcamp.com/fan-{id}") }
+}
+
+#[test]
+fn only_relationships_new_to_a_refresh_are_changed() {
+    let mut data = Data::default();
+    data.add_relationship(&album(1), &fan(1));
+    assert!(data.changed.is_empty());
+
+    data.refreshed(|data| {
+        data.add_relationship(&album(1), &fan(1));
+        data.add_relationship(&album(1), &fan(2));
+    });
+    data.add_relationship(&album(1), &fan(3));
+
+    let changed = data.changed.keys().map(|relationship| data.entities[relationship.to].data.url().to_owned()).collect::<Vec<_>>();
+    assert_eq!(changed, [fan(2).url]);
+}
+
+#[test]
+fn scraping_records_when_the_pages_were_fetched() {
+    let mut data = Data::default();
+    let id = data.add_node(&EntityData::Album(album(1)));
+    let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    data.set_scraped(album(1).into(), at);
+
+    assert_eq!(data.entities[id].state, ScrapeState::Done);
+    assert_eq!(data.entities[id].scraped_at, Some(at));
+}
+
+#[test]
+fn entities_scraped_on_their_own_are_added_to_the_graph() {
+    let mut data = Data::default();
+    let artist = Artist { id: ArtistId(1), url: "https://artist.bandcamp.com".to_owned() };
+
+    data.set_scraped(artist.clone().into(), SystemTime::UNIX_EPOCH);
+
+    assert_eq!(data.state(&artist.url), Some(&ScrapeState::Done));
+    assert_eq!(data.entities.len(), 1);
+}
+
+#[test]
+fn states_are_found_by_every_url_an_entity_had() {
+    let mut data = Data::default();
+    let alias = Album { url: "https://music.example.com/album/1".to_owned(), ..album(1) };
+    let id = data.add_node(&EntityData::Album(alias.clone()));
+    data.set_scraped(album(1).into(), SystemTime::UNIX_EPOCH);
+
+    assert_eq!(data.state(&alias.url), Some(&ScrapeState::Done));
+    assert_eq!(data.state(&album(1).url), Some(&ScrapeState::Done));
+    assert_eq!(data.urls[&album(1).url], id);
+    assert_eq!(data.state("https://artist.bandcamp.com/album/2"), None);
+}
+
+#[test]
+fn requests_move_entities_through_their_states() {
+    let mut data = Data::default();
+    let url = album(1).url;
+    data.add_node(&EntityData::Album(album(1)));
+
+    data.queued(&url);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Queued));
+    data.unqueued(&url);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Unscraped));
+
+    data.queued(&url);
+    data.started(&url);
+    data.failed(&url, "offline".to_owned(), None);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Failed { error: "offline".to_owned(), cursor: None }));
+
+    // A failed entity can be queued again, a scraped one is only ever re-scraped
+    data.queued(&url);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Queued));
+    data.started(&url);
+    data.set_scraped(album(1).into(), SystemTime::UNIX_EPOCH);
+    data.queued(&url);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Done));
+}
+
+#[test]
+fn cancelled_requests_leave_entities_as_they_were() {
+    let mut data = Data::default();
+    data.add_node(&EntityData::Album(album(1)));
+    data.add_node(&EntityData::Album(album(2)));
+    data.set_scraped(album(2).into(), SystemTime::UNIX_EPOCH);
+
+    for album in [album(1), album(2)] {
+        data.started(&album.url);
+        data.stopped(&album.url);
+    }
+
+    assert_eq!(data.state(&album(1).url), Some(&ScrapeState::Unscraped));
+    assert_eq!(data.state(&album(2).url), Some(&ScrapeState::Done));
+}
+
+#[test]
+fn failed_continuations_keep_their_cursor() {
+    let mut data = Data::default();
+    let url = album(1).url;
+    let cursor = Cursor::Fans { album: album(1), token: "1700000000:101".to_owned() };
+    data.add_node(&EntityData::Album(album(1)));
+    data.set_partial(album(1).into(), cursor.clone(), SystemTime::UNIX_EPOCH);
+
+    data.queued(&url);
+    assert_eq!(data.state(&url), Some(&ScrapeState::Partial(cursor.clone())));
+    data.started(&url);
+    data.failed(&url, "offline".to_owned(), Some(cursor.clone()));
+
+    let failed = ScrapeState::Failed { error: "offline".to_owned(), cursor: Some(cursor) };
+    assert_eq!(data.state(&url), Some(&failed));
+    data.queued(&url);
+    data.unqueued(&url);
+    assert_eq!(data.state(&url), Some(&failed));
+}
+
+#[test]
+fn followed_bands_turn_into_labels_once_known() {
+    let mut data = Data::default();
+    let band = Artist { id: ArtistId(1), url: "https://records.example.com/".to_owned() };
+    let label = Label { id: LabelId(1), url: band.url.clone() };
+    data.add_follow(&fan(1), &band);
+
+    data.add_roster(&Artist { id: ArtistId(2), url: "https://artist.bandcamp.com/".to_owned() }, &label);
+
+    assert!(data.artists.get(&band.id).is_none());
+    assert_eq!(data.urls[&label.url], data.labels[&label.id]);
+    assert_eq!(data.entities.into_iter().count(), 3);
+}
@@ -2,9 +2,18 @@ use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::time::Duration;
 use crate::{
     phys::{Acceleration, Velocity},
-    data::Data,
+    data::{Data, RelationshipKind},
 };
 
+/// How strongly each kind of relationship pulls its ends together, follows and recommendations are weaker signals
+/// than purchases and credits
+fn attraction(kind: RelationshipKind) -> f32 {
+    match kind {
+        RelationshipKind::Purchase | RelationshipKind::Release | RelationshipKind::Roster => 2.0,
+        RelationshipKind::Recommendation | RelationshipKind::Follow => 1.0,
+    }
+}
+
 fn update_position(data: &mut Data, delta: Duration) {
     for entity in &mut data.entities {
         entity.position += entity.velocity * delta;
@@ -34,11 +43,11 @@ fn repel(data: &mut Data) {
 
 fn attract(data: &mut Data) {
     for rel in &data.relationships {
-        let (album, user) = data.entities.index_pair(rel.album, rel.user);
+        let (from, to) = data.entities.index_pair(rel.from, rel.to);
         // TODO: Unit for attraction
-        let attraction = Acceleration::from((user.position - album.position).0 * 2.0);
-        album.acceleration += attraction / (album.related.len() as f32).sqrt();
-        user.acceleration += -attraction / (user.related.len() as f32).sqrt();
+        let attraction = Acceleration::from((to.position - from.position).0 * attraction(rel.kind));
+        from.acceleration += attraction / (from.related.len() as f32).sqrt();
+        to.acceleration += -attraction / (to.related.len() as f32).sqrt();
     }
 }
 
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use super::Response;
+
+/// Which response a later one of the same kind can be folded into
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Fans(String),
+    Collection(String),
+    Follows(String),
+    Recommended(String),
+    Roster(String),
+    Progress,
+}
+
+impl Key {
+    fn of(response: &Response) -> Option<Self> {
+        Some(match response {
+            Response::Fans(album, _) => Key::Fans(album.url.clone()),
+            Response::Collection(user, _) => Key::Collection(user.url.clone()),
+            Response::Follows(user, _) => Key::Follows(user.url.clone()),
+            Response::Recommended(album, _) => Key::Recommended(album.url.clone()),
+            Response::Roster(label, _) => Key::Roster(label.url.clone()),
+            Response::Progress { .. } => Key::Progress,
+            _ => return None,
+        })
+    }
+}
+
+/// Folds the relationships found for the same entity into its first response, so a batch adds each entity's links in
+/// one go
+///
+/// Responses only ever move earlier, so an entity's links are still added before it is marked scraped. Nothing is
+/// folded across the start of a request, so the ui can still tell which request each response belongs to. Progress is
+/// only kept as of the latest response for each request.
+pub fn merge(responses: impl IntoIterator<Item = Response>) -> Vec<Response> {
+    let mut merged = Vec::new();
+    let mut positions = HashMap::new();
+    for response in responses {
+        let Some(key) = Key::of(&response) else {
+            if matches!(response, Response::Started { .. } | Response::Idle) {
+                positions.clear();
+            }
+            merged.push(response);
+            continue;
+        };
+        let Some(&position) = positions.get(&key) else {
+            positions.insert(key, merged.len());
+            merged.push(response);
+            continue;
+        };
+        match (&mut merged[position], response) {
+            (Response::Fans(_, existing), Response::Fans(_, more)) => existing.extend(more),
+            (Response::Collection(_, existing), Response::Collection(_, more)) => existing.extend(more),
+            (Response::Follows(_, existing), Response::Follows(_, more)) => existing.extend(more),
+            (Response::Recommended(_, existing), Response::Recommended(_, more)) => existing.extend(more),
+            (Response::Roster(_, existing), Response::Roster(_, more)) => existing.extend(more),
+            (existing @ Response::Progress { .. }, latest @ Response::Progress { .. }) => *existing = latest,
+            _ => unreachable!("responses with the same key are the same kind"),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,48 @@
+use std::time::UNIX_EPOCH;
+use opt::data::{Album, AlbumId, ItemKind, User, UserId};
+
+use super::merge;
+use crate::background::Response;
+
+fn album(id: u64) -> Album {
+    Album { id: AlbumId(ItemKind::Album, id), url: format!("https://artist.bandcamp.com/album/{id}") }
+}
+
+fn fan(id: u64) -> User {
+    User { id: UserId(id), url: format!("https://bandcamp.com/fan-{id}") }
+}
+
+#[test]
+fn relationships_of_the_same_entity_are_folded_into_the_first() {
+    let merged = merge([
+        Response::Fans(album(1), vec![fan(1)]),
+        Response::Progress { fetched: 1, expected: None },
+        Response::Fans(album(2), vec![fan(2)]),
+        Response::Fans(album(1), vec![fan(3), fan(4)]),
+        Response::Progress { fetched: 3, expected: Some(3) },
+        Response::Scraped(album(1).into(), UNIX_EPOCH),
+    ]);
+
+    assert!(matches!(&merged[..], [
+        Response::Fans(first, fans),
+        Response::Progress { fetched: 3, expected: Some(3) },
+        Response::Fans(second, _),
+        Response::Scraped(..),
+    ] if *first == album(1) && *fans == [fan(1), fan(3), fan(4)] && *second == album(2)), "{merged:?}");
+}
+
+#[test]
+fn relationships_are_not_folded_across_requests() {
+    let merged = merge([
+        Response::Fans(album(1), vec![fan(1)]),
+        Response::Started { url: Some(album(1).url), queued: 0, refresh: true },
+        Response::Fans(album(1), vec![fan(2)]),
+        Response::Fans(album(1), vec![fan(3)]),
+    ]);
+
+    assert!(matches!(&merged[..], [
+        Response::Fans(_, before),
+        Response::Started { refresh: true, .. },
+        Response::Fans(_, during),
+    ] if *before == [fan(1)] && *during == [fan(2), fan(3)]), "{merged:?}");
+}
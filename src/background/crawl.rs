@@ -0,0 +1,235 @@
+use eyre::Error;
+use std::collections::{HashMap, HashSet};
+use opt::data::EntityData;
+
+use super::Request;
+
+/// Which frontier entity an automatic crawl scrapes next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Strategy {
+    /// Closest to a seed first
+    BreadthFirst,
+    /// Most links to already discovered entities first
+    HighestDegree,
+    /// Fewest scraped neighbours first, to fill in the sparsest parts of the graph
+    LeastScraped,
+}
+
+/// Limits of an automatic crawl, it stops expanding once any is reached
+#[derive(Debug, Clone)]
+pub struct CrawlConfig {
+    pub strategy: Strategy,
+    /// Links away from the nearest seed an entity may be to still be scraped
+    pub max_depth: usize,
+    /// Requests the crawl itself may make, not counting clicked or derived ones
+    pub max_requests: usize,
+    /// Entities the crawl may discover
+    pub max_nodes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+enum Kind {
+    Album,
+    User,
+    Artist,
+    Label,
+}
+
+impl Kind {
+    fn of(entity: &EntityData) -> Self {
+        match entity {
+            EntityData::Album(_) => Kind::Album,
+            EntityData::User(_) => Kind::User,
+            EntityData::Artist(_) => Kind::Artist,
+            EntityData::Label(_) => Kind::Label,
+        }
+    }
+
+    fn of_request(request: &Request) -> Option<Self> {
+        match request {
+            Request::Album { .. } => Some(Kind::Album),
+            Request::User { .. } => Some(Kind::User),
+            Request::Artist { .. } => Some(Kind::Artist),
+            Request::Label { .. } => Some(Kind::Label),
+            _ => None,
+        }
+    }
+
+    fn request(self, url: String) -> Request {
+        match self {
+            Kind::Album => Request::Album { url },
+            Kind::User => Request::User { url },
+            Kind::Artist => Request::Artist { url },
+            Kind::Label => Request::Label { url },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+enum State {
+    Frontier,
+    /// Handed out by [`Crawl::next`], but not yet handled
+    Crawled,
+    Requested,
+    Scraped,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Node {
+    kind: Kind,
+    depth: usize,
+    /// When the node was discovered, to break ties in the order it was found
+    discovered: usize,
+    state: State,
+    neighbours: HashSet<String>,
+}
+
+/// The entities discovered by an automatic crawl, keyed by url
+///
+/// Only entities within the depth limit are tracked, so degrees only count links between those. Only the progress is
+/// serialized, a restored crawl carries on with whatever limits it is restored with.
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Crawl {
+    #[serde(skip)]
+    config: CrawlConfig,
+    nodes: HashMap<String, Node>,
+    requests: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct Saved {
+    nodes: HashMap<String, Node>,
+    requests: usize,
+}
+
+impl Crawl {
+    pub(crate) fn new(config: CrawlConfig) -> Self {
+        Self { config, nodes: HashMap::new(), requests: 0 }
+    }
+
+    /// Picks a crawl saved by a previous run back up
+    #[fehler::throws]
+    pub(crate) fn restore(config: CrawlConfig, saved: serde_json::Value) -> Self {
+        let Saved { nodes, requests } = serde_json::from_value(saved)?;
+        Self { config, nodes, requests }
+    }
+
+    /// Starts crawling from an explicitly requested entity
+    pub(crate) fn seed(&mut self, request: &Request, url: &str) {
+        if let Some(kind) = Kind::of_request(request) {
+            self.discover(url, kind, 0);
+        }
+    }
+
+    /// Records a link found while scraping `from`, discovering `to` one step further from the seeds
+    ///
+    /// Links from entities the crawl doesn't track are ignored, only [`Crawl::seed`] starts a new neighbourhood.
+    pub(crate) fn link(&mut self, from: &EntityData, to: &EntityData) {
+        self.link_url(from, to.url(), Kind::of(to));
+    }
+
+    /// Like [`Crawl::link`] for a release only known by its url
+    pub(crate) fn link_release(&mut self, from: Option<&EntityData>, url: &str) {
+        match from {
+            Some(from) => self.link_url(from, url, Kind::Album),
+            // Releases of a tag have no entity to link from, they are as good as seeds
+            None => {
+                self.discover(url, Kind::Album, 0);
+            }
+        }
+    }
+
+    fn link_url(&mut self, from: &EntityData, to: &str, kind: Kind) {
+        // Entities past the depth limit, or only ever requested as derived from one, don't expand the crawl
+        let Some(depth) = self.nodes.get(from.url()).map(|node| node.depth) else { return };
+        if depth >= self.config.max_depth {
+            return;
+        }
+        if self.discover(to, kind, depth + 1) <= self.config.max_depth {
+            self.nodes.get_mut(from.url()).expect("discovered").neighbours.insert(to.to_owned());
+            self.nodes.get_mut(to).expect("discovered").neighbours.insert(from.url().to_owned());
+        }
+    }
+
+    /// Tracks an entity if it is within the limits, returning its depth
+    fn discover(&mut self, url: &str, kind: Kind, depth: usize) -> usize {
+        let discovered = self.nodes.len();
+        match self.nodes.get_mut(url) {
+            Some(node) => {
+                node.depth = node.depth.min(depth);
+                node.depth
+            }
+            None if depth > self.config.max_depth || discovered >= self.config.max_nodes => usize::MAX,
+            None => {
+                self.nodes.insert(url.to_owned(), Node { kind, depth, discovered, state: State::Frontier, neighbours: HashSet::new() });
+                depth
+            }
+        }
+    }
+
+    /// Merges the entity requested as `requested` into its canonical url, once scraping it shows they differ
+    pub(crate) fn canonical(&mut self, requested: &str, canonical: &str) {
+        if requested == canonical {
+            return;
+        }
+        let Some(node) = self.nodes.remove(requested) else { return };
+        for neighbour in &node.neighbours {
+            if let Some(neighbour) = self.nodes.get_mut(neighbour) {
+                neighbour.neighbours.remove(requested);
+                neighbour.neighbours.insert(canonical.to_owned());
+            }
+        }
+        match self.nodes.get_mut(canonical) {
+            Some(existing) => {
+                existing.depth = existing.depth.min(node.depth);
+                existing.state = existing.state.max(node.state);
+                existing.neighbours.extend(node.neighbours);
+            }
+            None => {
+                self.nodes.insert(canonical.to_owned(), node);
+            }
+        }
+    }
+
+    /// Keeps the crawl from requesting an entity that was requested some other way
+    ///
+    /// Called as a request is handled, which is when one the crawl handed out counts against its request limit. Those
+    /// dropped before then, like entities that turn out to be scraped already, cost nothing.
+    pub(crate) fn requested(&mut self, url: &str) {
+        if let Some(node) = self.nodes.get_mut(url) {
+            if node.state == State::Crawled {
+                self.requests += 1;
+            }
+            node.state = node.state.max(State::Requested);
+        }
+    }
+
+    pub(crate) fn scraped(&mut self, url: &str) {
+        if let Some(node) = self.nodes.get_mut(url) {
+            node.state = State::Scraped;
+        }
+    }
+
+    /// Picks the next frontier entity to scrape, `None` once the frontier is empty or the request budget spent
+    pub(crate) fn next(&mut self) -> Option<Request> {
+        if self.requests >= self.config.max_requests {
+            return None;
+        }
+        let frontier = self.nodes.iter().filter(|(_, node)| node.state == State::Frontier);
+        let (url, _) = match self.config.strategy {
+            Strategy::BreadthFirst => frontier.min_by_key(|(_, node)| (node.depth, node.discovered)),
+            Strategy::HighestDegree => frontier.min_by_key(|(_, node)| (std::cmp::Reverse(node.neighbours.len()), node.discovered)),
+            Strategy::LeastScraped => frontier.min_by_key(|(_, node)| {
+                let scraped = node.neighbours.iter().filter(|url| self.nodes.get(*url).is_some_and(|node| node.state == State::Scraped)).count();
+                (scraped, node.discovered)
+            }),
+        }?;
+        let url = url.clone();
+        let node = self.nodes.get_mut(&url).expect("picked from nodes");
+        node.state = State::Crawled;
+        Some(node.kind.request(url))
+    }
+}
+
+#[cfg(test)]
+mod tests;
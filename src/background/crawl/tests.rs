@@ -0,0 +1,145 @@
+use opt::data::{Album, AlbumId, EntityData, ItemKind, User, UserId};
+
+use super::{Crawl, CrawlConfig, Strategy};
+use crate::background::Request;
+
+fn crawl(strategy: Strategy, max_depth: usize) -> Crawl {
+    Crawl::new(CrawlConfig { strategy, max_depth, max_requests: 100, max_nodes: 100 })
+}
+
+fn user(id: u64) -> EntityData {
+    User { id: UserId(id), url: format!("https://bandcamp.com/fan-{id}") }.into()
+}
+
+fn album(id: u64) -> EntityData {
+    Album { id: AlbumId(ItemKind::Album, id), url: format!("https://artist.bandcamp.com/album/{id}") }.into()
+}
+
+fn seed(crawl: &mut Crawl, entity: &EntityData) {
+    crawl.seed(&Request::User { url: entity.url().to_owned() }, entity.url());
+}
+
+/// Takes every request the crawl hands out, handling each before the next like the background does
+fn drain(crawl: &mut Crawl) -> Vec<String> {
+    std::iter::from_fn(|| {
+        let url = crawl.next()?.url()?;
+        crawl.requested(&url);
+        Some(url)
+    }).collect()
+}
+
+#[test]
+fn restored_crawls_carry_on_where_they_left_off() {
+    let mut crawl = crawl(Strategy::BreadthFirst, 2);
+    seed(&mut crawl, &user(1));
+    assert_eq!(drain(&mut crawl), [user(1).url()]);
+    crawl.link(&user(1), &album(10));
+    crawl.link(&user(1), &album(11));
+    crawl.scraped(user(1).url());
+
+    let config = CrawlConfig { strategy: Strategy::BreadthFirst, max_depth: 2, max_requests: 2, max_nodes: 100 };
+    let mut restored = Crawl::restore(config, serde_json::to_value(&crawl).unwrap()).unwrap();
+    // The request already made counts against the restored limit
+    assert_eq!(drain(&mut restored), [album(10).url()]);
+}
+
+#[test]
+fn breadth_first_expands_closest_to_the_seeds_first() {
+    let mut crawl = crawl(Strategy::BreadthFirst, 2);
+    seed(&mut crawl, &user(1));
+    assert_eq!(drain(&mut crawl), [user(1).url()]);
+
+    crawl.link(&user(1), &album(10));
+    crawl.link(&user(1), &album(11));
+    crawl.scraped(user(1).url());
+    assert_eq!(crawl.next().and_then(|request| request.url()).as_deref(), Some(album(10).url()));
+
+    crawl.link(&album(10), &user(2));
+    crawl.link(&user(2), &album(12));
+    assert_eq!(drain(&mut crawl), [album(11).url(), user(2).url()]);
+}
+
+#[test]
+fn highest_degree_prefers_the_most_linked() {
+    let mut crawl = crawl(Strategy::HighestDegree, 2);
+    for id in [1, 2] {
+        seed(&mut crawl, &user(id));
+        crawl.requested(user(id).url());
+    }
+    crawl.link(&user(1), &album(10));
+    crawl.link(&user(1), &album(11));
+    crawl.link(&user(2), &album(11));
+
+    assert_eq!(drain(&mut crawl), [album(11).url(), album(10).url()]);
+}
+
+#[test]
+fn least_scraped_fills_in_the_sparsest_neighbourhoods() {
+    let mut crawl = crawl(Strategy::LeastScraped, 2);
+    for id in [1, 2, 3] {
+        seed(&mut crawl, &user(id));
+        crawl.requested(user(id).url());
+    }
+    crawl.link(&user(1), &album(10));
+    crawl.link(&user(2), &album(10));
+    crawl.link(&user(3), &album(11));
+    crawl.scraped(user(1).url());
+    crawl.scraped(user(2).url());
+
+    assert_eq!(drain(&mut crawl), [album(11).url(), album(10).url()]);
+}
+
+#[test]
+fn depth_and_request_limits() {
+    let mut crawl = crawl(Strategy::BreadthFirst, 1);
+    seed(&mut crawl, &user(1));
+    crawl.link(&user(1), &album(10));
+    crawl.link(&album(10), &user(2));
+    assert_eq!(drain(&mut crawl), [user(1).url(), album(10).url()]);
+
+    let mut crawl = Crawl::new(CrawlConfig { strategy: Strategy::BreadthFirst, max_depth: 2, max_requests: 1, max_nodes: 100 });
+    seed(&mut crawl, &user(1));
+    seed(&mut crawl, &user(2));
+    assert_eq!(drain(&mut crawl), [user(1).url()]);
+}
+
+#[test]
+fn requests_dropped_before_being_handled_are_not_counted() {
+    let mut crawl = Crawl::new(CrawlConfig { strategy: Strategy::BreadthFirst, max_depth: 2, max_requests: 1, max_nodes: 100 });
+    seed(&mut crawl, &user(1));
+    seed(&mut crawl, &user(2));
+
+    // The first was already scraped, so the background drops it without handling it
+    assert_eq!(crawl.next().and_then(|request| request.url()).as_deref(), Some(user(1).url()));
+    assert_eq!(drain(&mut crawl), [user(2).url()]);
+}
+
+#[test]
+fn derived_requests_at_the_depth_limit_expand_nothing() {
+    let mut crawl = crawl(Strategy::BreadthFirst, 1);
+    seed(&mut crawl, &user(1));
+    crawl.link(&user(1), &album(10));
+    crawl.link_release(Some(&user(1)), album(11).url());
+    assert_eq!(drain(&mut crawl), [user(1).url(), album(10).url(), album(11).url()]);
+
+    // The fans of an album at the limit aren't tracked, so scraping one the ui queued as derived doesn't make it a seed
+    crawl.link(&album(10), &user(2));
+    crawl.link(&user(2), &album(12));
+    crawl.link_release(Some(&user(2)), album(13).url());
+    assert_eq!(drain(&mut crawl), Vec::<String>::new());
+}
+
+#[test]
+fn canonical_urls_keep_depth_and_links() {
+    let mut crawl = crawl(Strategy::HighestDegree, 1);
+    seed(&mut crawl, &user(1));
+    crawl.requested(user(1).url());
+    crawl.link_release(Some(&user(1)), "https://artist.bandcamp.com/album/10?from=fan");
+    crawl.link(&user(1), &album(11));
+    crawl.canonical("https://artist.bandcamp.com/album/10?from=fan", album(10).url());
+    seed(&mut crawl, &user(2));
+    crawl.requested(user(2).url());
+    crawl.link(&user(2), &album(10));
+
+    assert_eq!(drain(&mut crawl), [album(10).url(), album(11).url()]);
+}
@@ -0,0 +1,259 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+/// Tracks json fields that don't match the structs we deserialize into, per endpoint, across a crawl
+///
+/// Unknown fields are silently ignored by serde, and missing fields fail the whole page, so without this a change to
+/// Bandcamp's json is only noticed once it has corrupted or stalled a crawl.
+#[derive(Debug, Default)]
+pub(crate) struct Drift {
+    strict: bool,
+    endpoints: RefCell<BTreeMap<String, Fields>>,
+}
+
+#[derive(Debug, Default)]
+struct Fields {
+    unknown: BTreeMap<String, usize>,
+    missing: BTreeMap<String, usize>,
+}
+
+impl Drift {
+    /// In strict mode any drift fails the page it was found on, rather than only being reported
+    pub(crate) fn new(strict: bool) -> Self {
+        Self { strict, ..Self::default() }
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Records a field `T` doesn't have, returning its path unless it is one we know about and deliberately ignore
+    pub(crate) fn unknown(&self, endpoint: &str, path: &serde_ignored::Path<'_>) -> Option<String> {
+        let path = normalize(path);
+        if is_known_ignored(endpoint, &path) {
+            return None;
+        }
+        *self.endpoints.borrow_mut().entry(endpoint.to_owned()).or_default().unknown.entry(path.clone()).or_default() += 1;
+        Some(path)
+    }
+
+    pub(crate) fn missing(&self, endpoint: &str, path: String) {
+        *self.endpoints.borrow_mut().entry(endpoint.to_owned()).or_default().missing.entry(path).or_default() += 1;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fields(&self, endpoint: &str) -> (Vec<String>, Vec<String>) {
+        self.endpoints.borrow().get(endpoint).map(|fields| (
+            fields.unknown.keys().cloned().collect(),
+            fields.missing.keys().cloned().collect(),
+        )).unwrap_or_default()
+    }
+
+    /// Logs everything seen so far, intended to be called at the end of a crawl or before it is restarted
+    pub(crate) fn report(&self) {
+        let endpoints = self.endpoints.borrow();
+        if endpoints.is_empty() {
+            tracing::info!("no schema drift seen");
+            return;
+        }
+        for (endpoint, fields) in &*endpoints {
+            for (path, count) in &fields.unknown {
+                tracing::warn!(%endpoint, %path, count, "unknown field");
+            }
+            for (path, count) in &fields.missing {
+                tracing::warn!(%endpoint, %path, count, "missing field");
+            }
+        }
+    }
+}
+
+/// Fields each endpoint sends that we deliberately don't model, only fields outside these count as drift
+///
+/// Paths are as rendered by [`normalize`].
+const KNOWN_IGNORED: &[(&str, &[&str])] = &[
+    ("BandData", &["account_id", "fan_email", "name"]),
+    ("ClientItem", &["artist", "band_id", "id", "title", "type"]),
+    ("Collections", &["items[].fan_id", "items[].item_type", "items[].purchased", "items[].token", "items[].tralbum_id"]),
+    ("Collectors", &["more_reviews_available", "reviews[].name", "reviews[].why", "thumbs[].name"]),
+    ("Discover", &["result_count", "results[].band_name", "results[].id", "results[].item_type", "results[].title"]),
+    ("FanPage", &[
+        "collection_data.item_count",
+        "fan_data.is_own_page",
+        "fan_data.name",
+        "following_bands_data.pending_unfollows",
+        "item_cache.collection.{}.fan_id",
+        "item_cache.collection.{}.item_type",
+        "item_cache.collection.{}.purchased",
+        "item_cache.collection.{}.token",
+        "item_cache.collection.{}.tralbum_id",
+        "item_cache.following_bands.{}.is_following",
+        "item_cache.following_bands.{}.location",
+        "item_cache.following_bands.{}.name",
+        "item_cache.following_bands.{}.token",
+        "item_cache.following_bands.{}.url_hints.custom_domain_verified",
+        "item_cache.following_bands.{}.url_hints.slug",
+    ]),
+    ("Following", &[
+        "followeebands[].is_following",
+        "followeebands[].location",
+        "followeebands[].name",
+        "followeebands[].token",
+        "followeebands[].url_hints.custom_domain_verified",
+        "followeebands[].url_hints.slug",
+    ]),
+    ("LdJson", &[
+        "@type",
+        "name",
+        "byArtist.@type",
+        "byArtist.name",
+        "byArtist.additionalProperty[].@type",
+        "publisher.@type",
+        "publisher.name",
+        "publisher.additionalProperty[].@type",
+    ]),
+    ("Properties", &["tralbum_page_version"]),
+    ("SearchItem", &["page", "rank"]),
+    ("Thumbs", &["results[].name"]),
+];
+
+fn is_known_ignored(endpoint: &str, path: &str) -> bool {
+    // Lists of items are checked against the item's own fields
+    let (endpoint, path) = match endpoint.strip_prefix("Vec<").and_then(|item| item.strip_suffix('>')) {
+        Some(item) => (item, path.strip_prefix("[].").unwrap_or(path)),
+        None => (endpoint, path),
+    };
+    KNOWN_IGNORED.iter().any(|(known, paths)| *known == endpoint && paths.contains(&path))
+}
+
+/// Whether a map key is an id rather than a field name, i.e. a number optionally prefixed by an item type letter like
+/// the `a123` keys of a fan's item cache
+fn is_id(key: &str) -> bool {
+    let digits = match key.as_bytes() {
+        [prefix, rest @ ..] if prefix.is_ascii_alphabetic() => rest,
+        bytes => bytes,
+    };
+    !digits.is_empty() && digits.iter().all(u8::is_ascii_digit)
+}
+
+fn join(parent: String, key: &str) -> String {
+    let key = if is_id(key) { "{}" } else { key };
+    if parent.is_empty() { key.to_owned() } else { format!("{parent}.{key}") }
+}
+
+/// Renders a path with sequence indexes and id map keys collapsed, so repeated items count as one field
+fn normalize(path: &serde_ignored::Path<'_>) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, .. } => format!("{}[]", normalize(parent)),
+        serde_ignored::Path::Map { parent, key } => join(normalize(parent), key),
+        serde_ignored::Path::Some { parent } | serde_ignored::Path::NewtypeStruct { parent } | serde_ignored::Path::NewtypeVariant { parent } => normalize(parent),
+    }
+}
+
+/// Renders a `serde_path_to_error` path the same way as [`normalize`]
+fn normalize_segments(path: &serde_path_to_error::Path) -> String {
+    let mut normalized = String::new();
+    for segment in path.iter() {
+        match segment {
+            serde_path_to_error::Segment::Seq { .. } => normalized.push_str("[]"),
+            serde_path_to_error::Segment::Map { key } | serde_path_to_error::Segment::Enum { variant: key } => {
+                normalized = join(normalized, key);
+            }
+            serde_path_to_error::Segment::Unknown => normalized = join(normalized, "?"),
+        }
+    }
+    normalized
+}
+
+/// Finds the field `T` requires that `text` lacks, if that is why it failed to deserialize
+///
+/// `serde_json` only reports a missing field in its error message, so the already parsed json is deserialized again
+/// through [`Probe`], whose error type keeps the field serde asked for.
+pub(crate) fn missing_field<T: serde::de::DeserializeOwned>(text: &str) -> Option<String> {
+    let value = serde_json::from_str(text).ok()?;
+    let error = serde_path_to_error::deserialize::<_, T>(Probe(value)).err()?;
+    let ProbeError::Missing(field) = error.inner() else { return None };
+    Some(join(normalize_segments(error.path()), field))
+}
+
+#[derive(Debug)]
+enum ProbeError {
+    Missing(&'static str),
+    Other(String),
+}
+
+impl serde::de::Error for ProbeError {
+    fn custom<T: std::fmt::Display>(message: T) -> Self {
+        ProbeError::Other(message.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ProbeError::Missing(field)
+    }
+}
+
+impl std::fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeError::Missing(field) => write!(f, "missing field {field}"),
+            ProbeError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+/// Deserializes a parsed json value with [`ProbeError`]
+struct Probe(serde_json::Value);
+
+impl<'de> serde::de::IntoDeserializer<'de, ProbeError> for Probe {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> serde::Deserializer<'de> for Probe {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        use serde::de::value::{MapDeserializer, SeqDeserializer};
+        use serde_json::Value;
+        match self.0 {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => visitor.visit_u64(n),
+                (None, Some(n)) => visitor.visit_i64(n),
+                (None, None) => visitor.visit_f64(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => visitor.visit_string(s),
+            Value::Array(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter().map(Probe))),
+            Value::Object(map) => visitor.visit_map(MapDeserializer::new(map.into_iter().map(|(key, value)| (key, Probe(value))))),
+        }
+    }
+
+    fn deserialize_option<V: serde::de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        match self.0 {
+            serde_json::Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: serde::de::Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, ProbeError> {
+        match self.0 {
+            serde_json::Value::String(variant) => visitor.visit_enum(serde::de::IntoDeserializer::<ProbeError>::into_deserializer(variant)),
+            value => Err(serde::de::Error::custom(format!("expected {name} as one of {variants:?}, got {value}"))),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: serde::de::Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
@@ -0,0 +1,109 @@
+use url::Url;
+
+/// How much of the offending page to keep around the point of failure
+const SNIPPET_RADIUS: usize = 120;
+
+/// A failure scraping a single page, with enough context to tell markup changes apart from network issues
+#[derive(Debug)]
+pub(crate) struct ScrapeError {
+    pub(crate) kind: ErrorKind,
+    pub(crate) url: Url,
+    /// Row in the web cache the page was served from, if it got that far
+    pub(crate) cache_id: Option<i64>,
+    pub(crate) snippet: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ErrorKind {
+    Network(reqwest::Error),
+    Status(reqwest::StatusCode),
+    MissingSelector { selector: String },
+    MissingAttribute { element: String, attribute: String },
+    Json(serde_path_to_error::Error<serde_json::Error>),
+    /// An attribute is there but doesn't hold what we expect, e.g. an id that isn't a number
+    InvalidAttribute { element: String, attribute: String, value: String },
+    /// A list names an item the page doesn't include, e.g. a collection sequence entry without an item cache entry
+    MissingItem { list: &'static str, key: String },
+    Pagination { reason: String },
+    SchemaDrift { endpoint: String, unknown: Vec<String> },
+}
+
+impl ScrapeError {
+    pub(crate) fn new(kind: ErrorKind, url: Url) -> Self {
+        Self { kind, url, cache_id: None, snippet: None }
+    }
+
+    /// Whether this looks like Bandcamp changed their pages, rather than a transient failure
+    pub(crate) fn is_markup_change(&self) -> bool {
+        match self.kind {
+            ErrorKind::Network(_) | ErrorKind::Status(_) => false,
+            ErrorKind::MissingSelector { .. }
+            | ErrorKind::MissingAttribute { .. }
+            | ErrorKind::InvalidAttribute { .. }
+            | ErrorKind::MissingItem { .. }
+            | ErrorKind::Json(_)
+            | ErrorKind::Pagination { .. }
+            | ErrorKind::SchemaDrift { .. } => true,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Network(error) => write!(f, "network error: {error}"),
+            ErrorKind::Status(status) => write!(f, "unexpected http status {status}"),
+            ErrorKind::MissingSelector { selector } => write!(f, "missing element for {selector}"),
+            ErrorKind::MissingAttribute { element, attribute } => write!(f, "missing {attribute} on <{element}>"),
+            ErrorKind::Json(error) => write!(f, "bad json at {}: {}", error.path(), error.inner()),
+            ErrorKind::InvalidAttribute { element, attribute, value } => write!(f, "unexpected {attribute}={value:?} on <{element}>"),
+            ErrorKind::MissingItem { list, key } => write!(f, "{list} references {key} missing from the item cache"),
+            ErrorKind::Pagination { reason } => write!(f, "unexpected pagination state: {reason}"),
+            ErrorKind::SchemaDrift { endpoint, unknown } => write!(f, "unknown fields in {endpoint}: {}", unknown.join(", ")),
+        }
+    }
+}
+
+impl std::fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (url: {}", self.kind, self.url)?;
+        if let Some(cache_id) = self.cache_id {
+            write!(f, ", cache row: {cache_id}")?;
+        }
+        write!(f, ")")?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n    near: {snippet}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScrapeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Network(error) => Some(error),
+            ErrorKind::Json(error) => Some(error.inner()),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the text surrounding `offset` with whitespace collapsed, for including in error messages
+pub(crate) fn snippet(text: &str, offset: usize) -> String {
+    let floor = |mut i: usize| {
+        i = i.min(text.len());
+        while !text.is_char_boundary(i) {
+            i -= 1;
+        }
+        i
+    };
+    let start = floor(offset.saturating_sub(SNIPPET_RADIUS));
+    let end = floor(offset.saturating_add(SNIPPET_RADIUS));
+    text[start..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Converts a 1-based line/column position as reported by `serde_json` into a byte offset
+pub(crate) fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+    line_start + column.saturating_sub(1)
+}
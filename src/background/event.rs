@@ -0,0 +1,211 @@
+use eyre::Result;
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+pub use opt::data::Cursor;
+use opt::data::{Album, Artist, EntityData, Label, Purchase, User};
+
+/// Something learnt while scraping, in the order the pages were fetched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event {
+    /// The entity being scraped, with its canonical url
+    Header(EntityData),
+    /// How many fans or collection items the crawl should find in total, when the first page says
+    Expected(usize),
+    Credit(Album, Artist, Option<Label>),
+    Recommended(Album, Vec<Album>),
+    Fans(Album, Vec<User>),
+    Collection(User, Vec<Purchase>),
+    Follows(User, Vec<Artist>),
+    Roster(Label, Vec<Artist>),
+    /// The label an artist's page links back to
+    Signed(Artist, Label),
+    Release(String),
+    /// Every page has been fetched, only emitted if none failed
+    Done,
+    /// The budget ran out or the crawl was cancelled before every page was fetched, it can be resumed from the cursor
+    Partial(Cursor),
+    /// The crawl was cancelled before a page it can't be resumed from
+    Cancelled,
+}
+
+/// Limits on how much a single request may crawl before it is left partially scraped
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub max_fans: usize,
+    pub max_collection_items: usize,
+    pub max_pages: usize,
+    pub max_time: Duration,
+}
+
+/// Tells a crawl to stop before fetching its next page
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The events from one fetched page, and how to fetch the page after it
+pub(crate) struct Fetched<'a> {
+    pub(crate) events: Vec<Event>,
+    pub(crate) next: Option<Step<'a>>,
+}
+
+/// A page that has yet to be fetched
+pub(crate) struct Step<'a> {
+    fetch: Box<dyn FnOnce() -> Result<Fetched<'a>> + 'a>,
+    /// How to get back to this page later, only set for pages a budget is allowed to cut off
+    cursor: Option<Cursor>,
+}
+
+impl<'a> Step<'a> {
+    pub(crate) fn new(fetch: impl FnOnce() -> Result<Fetched<'a>> + 'a) -> Self {
+        Self { fetch: Box::new(fetch), cursor: None }
+    }
+
+    pub(crate) fn resumable(cursor: Cursor, fetch: impl FnOnce() -> Result<Fetched<'a>> + 'a) -> Self {
+        Self { fetch: Box::new(fetch), cursor: Some(cursor) }
+    }
+}
+
+impl<'a> Fetched<'a> {
+    pub(crate) fn last(events: Vec<Event>) -> Self {
+        Self { events, next: None }
+    }
+
+    pub(crate) fn then(events: Vec<Event>, next: Step<'a>) -> Self {
+        Self { events, next: Some(next) }
+    }
+}
+
+/// A lazy crawl, each page is only fetched once the events from the previous one have been consumed
+///
+/// Iteration stops after the first error, so an `Ok(Event::Done)` means the whole crawl succeeded.
+pub(crate) struct Events<'a> {
+    pending: VecDeque<Event>,
+    next: Option<Step<'a>>,
+    finished: bool,
+    budget: Option<Budget>,
+    cancel: Option<CancelToken>,
+    started: Instant,
+    pages: usize,
+    fans: usize,
+    collection_items: usize,
+}
+
+impl<'a> Events<'a> {
+    pub(crate) fn new(first: Step<'a>) -> Self {
+        Self::resume(Vec::new(), first)
+    }
+
+    /// Continues a crawl, starting with events already known from earlier pages
+    pub(crate) fn resume(events: Vec<Event>, next: Step<'a>) -> Self {
+        Self {
+            pending: events.into(),
+            next: Some(next),
+            finished: false,
+            budget: None,
+            cancel: None,
+            started: Instant::now(),
+            pages: 0,
+            fans: 0,
+            collection_items: 0,
+        }
+    }
+
+    /// Stops the crawl with [`Event::Partial`] before fetching a resumable page once any limit has been reached
+    pub(crate) fn with_budget(self, budget: Budget) -> Self {
+        Self { budget: Some(budget), ..self }
+    }
+
+    /// Stops the crawl before fetching its next page once the token is cancelled, with [`Event::Partial`] if it can be
+    /// resumed from there or [`Event::Cancelled`] otherwise
+    pub(crate) fn with_cancel(self, cancel: CancelToken) -> Self {
+        Self { cancel: Some(cancel), ..self }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+
+    fn is_over_budget(&self) -> bool {
+        self.budget.as_ref().is_some_and(|budget| {
+            self.pages >= budget.max_pages
+                || self.fans >= budget.max_fans
+                || self.collection_items >= budget.max_collection_items
+                || self.started.elapsed() >= budget.max_time
+        })
+    }
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.next.take() {
+                Some(Step { cursor: Some(cursor), .. }) if self.is_cancelled() => {
+                    tracing::info!(pages = self.pages, "scrape cancelled");
+                    self.finished = true;
+                    return Some(Ok(Event::Partial(cursor)));
+                }
+                Some(Step { cursor: None, .. }) if self.is_cancelled() => {
+                    tracing::info!(pages = self.pages, "scrape cancelled");
+                    self.finished = true;
+                    return Some(Ok(Event::Cancelled));
+                }
+                Some(Step { cursor: Some(cursor), .. }) if self.is_over_budget() => {
+                    tracing::info!(pages = self.pages, fans = self.fans, collection_items = self.collection_items, elapsed = ?self.started.elapsed(), "scrape budget exhausted");
+                    self.finished = true;
+                    return Some(Ok(Event::Partial(cursor)));
+                }
+                Some(Step { fetch, .. }) => match fetch() {
+                    Ok(Fetched { events, next }) => {
+                        self.pages += 1;
+                        for event in &events {
+                            match event {
+                                Event::Fans(_, fans) => self.fans += fans.len(),
+                                Event::Collection(_, items) => self.collection_items += items.len(),
+                                _ => {}
+                            }
+                        }
+                        self.pending.extend(events);
+                        self.next = next;
+                    }
+                    Err(error) => {
+                        self.finished = true;
+                        return Some(Err(error));
+                    }
+                },
+                None => {
+                    self.finished = true;
+                    return Some(Ok(Event::Done));
+                }
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Events<'_> {}
+
+impl std::fmt::Debug for Events<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Events")
+            .field("pending", &self.pending)
+            .field("has_next", &self.next.is_some())
+            .field("finished", &self.finished)
+            .field("pages", &self.pages)
+            .finish()
+    }
+}
@@ -1,26 +1,128 @@
-use crossbeam::channel::{Sender, Receiver, SendError};
+use crossbeam::channel::{Sender, Receiver, RecvTimeoutError, SendError, TryRecvError};
 use eyre::Error;
 use url::Url;
-use std::cell::RefCell;
-use opt::data::{Album, User};
+use std::{cell::{Cell, RefCell}, collections::HashMap, panic::AssertUnwindSafe, time::{Duration, Instant, SystemTime}};
+pub use self::batch::merge;
+use self::{crawl::Crawl, event::{CancelToken, Event}, queue::Queue};
+pub use self::{crawl::{CrawlConfig, Strategy}, event::{Budget, Cursor}, queue::Priority};
+use opt::data::{Album, User, Artist, Label, Purchase, EntityData};
 
+mod batch;
+mod crawl;
+mod drift;
+mod error;
+mod event;
+mod queue;
 mod scrape;
 mod web;
 
-#[derive(Debug)]
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Request {
     User { url: String },
     Album { url: String },
     Artist { url: String },
+    Label { url: String },
+    Tag { tag: String },
+    Search { query: String, kind: SearchKind },
+    /// Carries on with an entity that was left partially scraped
+    Continue { cursor: Cursor },
+    /// Drops the entity from the queue, or stops it at its next page if it is being scraped
+    Cancel { url: String },
+    /// Drops every queued request and stops the one being scraped
+    CancelAll,
+}
+
+impl Request {
+    /// Scrapes the entity from scratch
+    pub fn scrape(entity: &EntityData) -> Self {
+        let url = entity.url().to_owned();
+        match entity {
+            EntityData::Album(_) => Request::Album { url },
+            EntityData::User(_) => Request::User { url },
+            EntityData::Artist(_) => Request::Artist { url },
+            EntityData::Label(_) => Request::Label { url },
+        }
+    }
+
+    /// The entity the request is about, if any
+    fn url(&self) -> Option<String> {
+        match self {
+            Request::User { url } | Request::Album { url } | Request::Artist { url } | Request::Label { url } => Some(url.clone()),
+            Request::Continue { cursor } => Some(cursor.entity().url().to_owned()),
+            Request::Tag { .. } | Request::Search { .. } | Request::Cancel { .. } | Request::CancelAll => None,
+        }
+    }
+}
+
+/// Which of Bandcamp's search tabs to look in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+pub enum SearchKind {
+    #[default]
+    All,
+    Albums,
+    Tracks,
+    /// Artists and labels
+    Artists,
+    Fans,
+}
+
+/// A search hit, not yet part of the graph until it is picked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub name: String,
+    pub entity: EntityData,
 }
 
 #[derive(Debug)]
 pub enum Response {
-    User(User),
-    Album(Album),
+    /// A request was taken off the queue, with the canonical url of the entity it is about if any, `refresh` if it is
+    /// re-scraping a stale entity
+    Started { url: Option<String>, queued: usize, refresh: bool },
+    /// Fans or collection items fetched so far by the started request, out of how many are expected if known
+    Progress { fetched: usize, expected: Option<usize> },
+    /// The started request stopped at an error, whatever was sent before it is still valid
+    ///
+    /// A failed continuation carries the cursor it started from, so it can be continued again.
+    Failed { url: Option<String>, error: String, cursor: Option<Cursor> },
+    /// Every queued request has been handled
+    Idle,
+    /// A request for the entity with this url is waiting behind others
+    Queued { url: String },
+    /// The queued request for the entity with this url was cancelled before it started
+    Unqueued { url: String },
+    /// Every page of the entity has been scraped, its data carries the canonical url, along with when its page was
+    /// fetched
+    Scraped(EntityData, SystemTime),
+    /// Scraping stopped at the request's budget, it can be continued with [`Request::Continue`]
+    Partial(EntityData, Cursor, SystemTime),
     Fans(Album, Vec<User>),
-    Collection(User, Vec<Album>),
+    Collection(User, Vec<Purchase>),
+    Follows(User, Vec<Artist>),
+    Credit(Album, Artist),
+    Recommended(Album, Vec<Album>),
+    Roster(Label, Vec<Artist>),
     Release(String),
+    Discography(String),
+    SearchResults(Vec<SearchResult>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of roster artists whose discographies are scraped per label
+    pub roster_limit: usize,
+    /// Number of top releases enqueued per tag
+    pub tag_limit: usize,
+    /// Fail pages with json fields we don't know about, instead of only reporting them at shutdown
+    pub strict_schema: bool,
+    /// How much each album, fan, artist or label request may crawl
+    pub budget: Budget,
+    /// Keep expanding the graph from the seeds whenever nothing is queued
+    pub crawl: Option<CrawlConfig>,
+    /// Re-scrape entities, and fetch cached pages again, once they are older than this
+    pub refresh_after: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -31,88 +133,581 @@ pub struct Thread {
 impl Thread {
     #[fehler::throws]
     pub fn spawn(
-        to_scrape: Receiver<Request>,
+        config: Config,
+        to_scrape: Receiver<(Request, Priority)>,
         scraped: Sender<Response>,
     ) -> Self {
-        let background = Background::new(to_scrape, scraped)?;
-        let thread = Some(std::thread::spawn(move || background.run()));
+        let background = Background::open(config.clone(), to_scrape.clone(), scraped.clone())?;
+        let thread = Some(std::thread::spawn(move || supervise(background, config, to_scrape, scraped)));
         Thread { thread }
     }
 }
 
 impl Drop for Thread {
     fn drop(&mut self) {
-        if let Err(e) = self.thread.take().unwrap().join() {
-            std::panic::resume_unwind(e);
+        if let Err(panic) = self.thread.take().unwrap().join() {
+            tracing::error!(panic = panic_message(&*panic), "background thread panicked while shutting down");
+        }
+    }
+}
+
+/// How often to look for stale entities while there's nothing else to do
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait before starting the background thread over, so a persistent failure doesn't spin
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs the background thread, starting it over from the saved queue whenever it panics outside of a request
+///
+/// What has been scraped and where the crawl got to carry over, so a restart doesn't scrape everything again.
+fn supervise(mut background: Background, config: Config, to_scrape: Receiver<(Request, Priority)>, scraped: Sender<Response>) {
+    loop {
+        let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(|| background.run())) else { return };
+        let error = format!("background thread panicked: {}", panic_message(&*panic));
+        tracing::error!(error, "restarting background thread");
+        // The restarted background parses with a new scraper, so what this one saw would otherwise never be reported
+        background.scraper.drift().report();
+        if scraped.send(Response::Failed { url: None, error, cursor: None }).is_err() {
+            return;
         }
+        let previous = background;
+        background = loop {
+            std::thread::sleep(RESTART_DELAY);
+            match Background::open(config.clone(), to_scrape.clone(), scraped.clone()) {
+                Ok(background) => break background.carry_over(previous),
+                Err(error) => {
+                    tracing::error!(?error, "failed restarting background thread");
+                    if scraped.send(Response::Failed { url: None, error: format!("failed restarting background thread: {error:#}"), cursor: None }).is_err() {
+                        return;
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// The canonical url of the entity a queued request is about, which its key was resolved to
+fn entity_url(key: &str, request: &Request) -> Option<String> {
+    request.url().map(|_| key.strip_prefix("continue:").unwrap_or(key).to_owned())
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        (None, None) => "unknown panic",
     }
 }
 
+/// An entity that has been scraped, and when its page was fetched
+#[derive(Debug, Clone)]
+struct Scraped {
+    at: SystemTime,
+    entity: EntityData,
+}
+
 #[derive(Debug)]
 struct Background {
+    config: Config,
     scraper: self::scrape::Scraper,
-    to_scrape: Receiver<Request>,
+    /// The entities scraped so far by both canonical and requested url, so the same page reached through different links
+    /// is only scraped once until it goes stale
+    ///
+    /// Unlike the queue and the crawl this isn't saved, the graph is built up again from the web cache on every run.
+    done: RefCell<HashMap<Url, Scraped>>,
+    last_stale_check: Cell<Instant>,
+    /// Requests received but not yet handled, deduplicated by canonical url
+    queue: RefCell<Queue<Request>>,
+    /// The queue key of the request being handled, and the token to stop its crawl
+    in_flight: RefCell<Option<(String, CancelToken)>>,
+    /// Where an automatic crawl has got to, if enabled
+    crawl: RefCell<Option<Crawl>>,
+    to_scrape: Receiver<(Request, Priority)>,
     scraped: Sender<Response>,
+    /// Requests for this url panic while being handled
+    #[cfg(test)]
+    panic_on: Option<String>,
 }
 
 impl Background {
+    /// Starts from the saved queue in the on-disk cache
+    #[fehler::throws]
+    fn open(
+        config: Config,
+        to_scrape: Receiver<(Request, Priority)>,
+        scraped: Sender<Response>,
+    ) -> Self {
+        let client = self::web::Client::new()?.with_max_age(config.refresh_after);
+        Self::new(config, client, to_scrape, scraped)?
+    }
+
     #[fehler::throws]
     fn new(
-        to_scrape: Receiver<Request>,
+        config: Config,
+        client: self::web::Client,
+        to_scrape: Receiver<(Request, Priority)>,
         scraped: Sender<Response>,
     ) -> Self {
-        let scraper = self::scrape::Scraper::new(self::web::Client::new()?);
-        Self {
+        let crawl = match config.crawl.clone() {
+            Some(crawl_config) => Some(match client.crawl(crawl_config.clone())? {
+                Some(crawl) => {
+                    tracing::info!("resuming crawl");
+                    crawl
+                }
+                None => Crawl::new(crawl_config),
+            }),
+            None => None,
+        };
+        let crawl = RefCell::new(crawl);
+        let scraper = self::scrape::Scraper::new(client, self::drift::Drift::new(config.strict_schema));
+        let background = Self {
+            config,
             scraper,
+            done: RefCell::default(),
+            last_stale_check: Cell::new(Instant::now()),
+            queue: RefCell::default(),
+            in_flight: RefCell::default(),
+            crawl,
             to_scrape,
             scraped,
+            #[cfg(test)]
+            panic_on: None,
+        };
+
+        let queued = background.scraper.client().queued()?;
+        tracing::info!(count = queued.len(), "resuming queued requests");
+        for (key, priority, request) in queued {
+            if priority >= Priority::Seed {
+                background.crawl(|crawl| crawl.seed(&request, &key));
+            }
+            background.queue.borrow_mut().push(key, priority, request);
+        }
+
+        background
+    }
+
+    /// Keeps what a background that panicked had scraped and crawled, on top of the queue restored from the cache
+    fn carry_over(self, previous: Background) -> Self {
+        Self {
+            done: previous.done,
+            last_stale_check: previous.last_stale_check,
+            crawl: previous.crawl,
+            ..self
+        }
+    }
+
+    /// Tells the ui about the entities a previous run left partially scraped, so they can be continued
+    #[fehler::throws]
+    fn restore_partials(&self) {
+        for cursor in self.scraper.client().partials()? {
+            let entity = cursor.entity();
+            let at = self.finish(None, &entity)?;
+            self.scraped.send(Response::Partial(entity, cursor, at))?;
         }
     }
 
     fn run(&self) {
-        for request in &self.to_scrape {
-            if let Err(error) = self.handle_request(request) {
+        if let Err(error) = self.restore_partials() {
+            tracing::error!(?error, "failed restoring partially scraped entities");
+        }
+        while let Some((key, priority, request)) = self.next_request() {
+            let url = entity_url(&key, &request);
+            let cursor = match &request {
+                Request::Continue { cursor } => Some(cursor.clone()),
+                _ => None,
+            };
+            let queued = self.queue.borrow().len();
+            if self.scraped.send(Response::Started { url: url.clone(), queued, refresh: priority == Priority::Refresh }).is_err() {
+                break;
+            }
+            self.crawl(|crawl| crawl.requested(&key));
+            *self.in_flight.borrow_mut() = Some((key, CancelToken::default()));
+            // A bug scraping one page shouldn't take every other request down with it
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| self.handle_request(request)))
+                .unwrap_or_else(|panic| Err(eyre::eyre!("panicked: {}", panic_message(&*panic))));
+            let (key, _) = self.in_flight.take().expect("in flight until handled");
+            self.save_crawl();
+            // Requests interrupted by shutting down stay saved, to be picked back up on the next run
+            let shutdown = result.as_ref().is_err_and(|error| error.is::<SendError<Response>>());
+            if !shutdown && self.queue.borrow().priority(&key).is_none() {
+                if let Err(error) = self.scraper.client().remove_queued(&key) {
+                    tracing::warn!(?error, key, "failed removing handled request");
+                }
+            }
+            if let Err(error) = result {
                 if error.is::<SendError<Response>>() {
                     tracing::info!("background thread shutdown while still processing an item");
-                    return;
+                    break;
+                }
+                match error.downcast_ref::<self::error::ScrapeError>() {
+                    Some(scrape) if scrape.is_markup_change() => {
+                        tracing::error!(%scrape, "failed parsing page, bandcamp markup may have changed");
+                    }
+                    Some(scrape) => {
+                        tracing::warn!(%scrape, "failed fetching page");
+                    }
+                    None => {
+                        tracing::error!(?error, "failed handling scrape request");
+                    }
+                }
+                if self.scraped.send(Response::Failed { url, error: format!("{error:#}"), cursor }).is_err() {
+                    break;
+                }
+            }
+        }
+        self.scraper.drift().report();
+    }
+
+    /// Waits for the most urgent pending request, returning `None` once the ui has hung up
+    fn next_request(&self) -> Option<(String, Priority, Request)> {
+        loop {
+            if !self.receive() {
+                return None;
+            }
+            if self.last_stale_check.get().elapsed() >= STALE_CHECK_INTERVAL {
+                self.requeue_stale();
+            }
+            if let Some(request) = self.queue.borrow_mut().pop() {
+                return Some(request);
+            }
+            let crawled = self.crawl.borrow_mut().as_mut().and_then(Crawl::next);
+            if let Some(request) = crawled {
+                self.enqueue(request, Priority::Derived);
+                continue;
+            }
+            self.scraped.send(Response::Idle).ok()?;
+            let received = match self.config.refresh_after {
+                Some(_) => self.to_scrape.recv_timeout(STALE_CHECK_INTERVAL),
+                None => self.to_scrape.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok((request, priority)) => self.enqueue(request, priority),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Queues entities scraped longer ago than the refresh age to be scraped again, behind everything else
+    fn requeue_stale(&self) {
+        self.last_stale_check.set(Instant::now());
+        let Some(max_age) = self.config.refresh_after else { return };
+        let mut stale = Vec::new();
+        self.done.borrow_mut().retain(|url, scraped| {
+            let is_stale = scraped.at.elapsed().is_ok_and(|age| age >= max_age);
+            // Every url an entity was reached by is forgotten, but it only needs scraping once
+            if is_stale && scraped.entity.url() == url.as_str() {
+                stale.push(scraped.entity.clone());
+            }
+            !is_stale
+        });
+        if !stale.is_empty() {
+            tracing::info!(count = stale.len(), "re-scraping stale entities");
+        }
+        for entity in stale {
+            self.enqueue(Request::scrape(&entity), Priority::Refresh);
+        }
+    }
+
+    /// Moves every request waiting in the channel into the queue, returning `false` if the ui has hung up
+    fn receive(&self) -> bool {
+        loop {
+            match self.to_scrape.try_recv() {
+                Ok((request, priority)) => self.enqueue(request, priority),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn enqueue(&self, request: Request, priority: Priority) {
+        match request {
+            Request::Cancel { url } => {
+                if let Err(error) = self.cancel(&url) {
+                    tracing::warn!(?error, %url, "failed cancelling");
+                }
+                return;
+            }
+            Request::CancelAll => return self.cancel_all(),
+            _ => {}
+        }
+        let key = match self.queue_key(&request) {
+            Ok(Some(key)) => key,
+            Ok(None) => return,
+            Err(error) => {
+                tracing::warn!(?error, ?request, "dropping invalid request");
+                return;
+            }
+        };
+        if priority >= Priority::Seed {
+            self.crawl(|crawl| crawl.seed(&request, &key));
+        }
+        let mut queue = self.queue.borrow_mut();
+        if queue.priority(&key).is_none_or(|queued| priority > queued) {
+            if let Err(error) = self.scraper.client().save_queued(&key, priority, &request) {
+                tracing::warn!(?error, key, "failed saving queued request");
+            }
+        }
+        let url = entity_url(&key, &request);
+        if !queue.push(key, priority, request) {
+            tracing::debug!(?priority, "already queued");
+        } else if let Some(url) = url {
+            self.scraped.send(Response::Queued { url }).ok();
+        }
+        tracing::trace!(queued = queue.len());
+    }
+
+    /// Identifies duplicate requests, `None` for entities that have already been scraped
+    #[fehler::throws]
+    fn queue_key(&self, request: &Request) -> Option<String> {
+        match request {
+            Request::User { url } | Request::Album { url } | Request::Artist { url } | Request::Label { url } => {
+                self.claim(url)?.map(String::from)
+            }
+            Request::Tag { tag } => Some(format!("tag:{tag}")),
+            Request::Search { query, kind } => Some(format!("search:{kind:?}:{query}")),
+            Request::Continue { cursor } => Some(format!("continue:{}", cursor.entity().url())),
+            // Handled as soon as they're received
+            Request::Cancel { .. } | Request::CancelAll => None,
+        }
+    }
+
+    fn save_crawl(&self) {
+        if let Some(crawl) = &*self.crawl.borrow() {
+            if let Err(error) = self.scraper.client().save_crawl(crawl) {
+                tracing::warn!(?error, "failed saving crawl");
+            }
+        }
+    }
+
+    fn crawl(&self, f: impl FnOnce(&mut Crawl)) {
+        if let Some(crawl) = self.crawl.borrow_mut().as_mut() {
+            f(crawl);
+        }
+    }
+
+    /// Tells an automatic crawl about the entities found linked to `from`
+    fn crawl_links<F, T>(&self, from: &F, to: impl IntoIterator<Item = T>)
+    where
+        F: Clone + Into<EntityData>,
+        T: Into<EntityData>,
+    {
+        self.crawl(|crawl| {
+            let from = from.clone().into();
+            for to in to {
+                crawl.link(&from, &to.into());
+            }
+        });
+    }
+
+    /// Cancels both a fresh scrape and a continuation of the entity, partial results are kept
+    #[fehler::throws]
+    fn cancel(&self, url: &str) {
+        let url = self.scraper.resolve(&Url::parse(url)?)?;
+        let keys = [url.to_string(), format!("continue:{url}")];
+        let mut queue = self.queue.borrow_mut();
+        for key in &keys {
+            if let Some(request) = queue.remove(key) {
+                tracing::info!(key, "cancelled queued request");
+                self.scraper.client().remove_queued(key)?;
+                self.unqueued(key, &request);
+            }
+        }
+        if let Some((key, cancel)) = &*self.in_flight.borrow() {
+            if keys.contains(key) {
+                tracing::info!(key, "cancelling in-flight request");
+                cancel.cancel();
+            }
+        }
+    }
+
+    fn cancel_all(&self) {
+        let mut queue = self.queue.borrow_mut();
+        tracing::info!(queued = queue.len(), "cancelling every request");
+        let cancelled = queue.clear();
+        if let Err(error) = self.scraper.client().clear_queued() {
+            tracing::warn!(?error, "failed removing saved requests");
+        }
+        if let Some((_, cancel)) = &*self.in_flight.borrow() {
+            cancel.cancel();
+        }
+        for (key, request) in &cancelled {
+            self.unqueued(key, request);
+        }
+    }
+
+    fn unqueued(&self, key: &str, request: &Request) {
+        if let Some(url) = entity_url(key, request) {
+            self.scraped.send(Response::Unqueued { url }).ok();
+        }
+    }
+
+    /// Resolves the url of a requested entity, returning `None` if it has already been scraped
+    #[fehler::throws]
+    fn claim(&self, url: &str) -> Option<Url> {
+        let url = self.scraper.resolve(&Url::parse(url)?)?;
+        if self.done.borrow().contains_key(&url) {
+            tracing::info!(%url, "already scraped");
+            None
+        } else {
+            Some(url)
+        }
+    }
+
+    /// Sends the responses for each scrape event, stopping at the first failed page
+    #[fehler::throws]
+    fn forward(&self, events: self::event::Events<'_>, requested: Option<Url>) {
+        let events = match &*self.in_flight.borrow() {
+            Some((_, cancel)) => events.with_cancel(cancel.clone()),
+            None => events,
+        };
+        let mut header = None;
+        let (mut fetched, mut expected) = (0, None);
+        for event in events {
+            // Keep queueing requests during long crawls, so duplicates are dropped and priorities raised in the meantime
+            self.receive();
+            match event? {
+                Event::Header(entity) => {
+                    if let Some(requested) = &requested {
+                        self.crawl(|crawl| crawl.canonical(requested.as_str(), entity.url()));
+                    }
+                    header = Some(entity);
+                }
+                Event::Expected(total) => expected = Some(total),
+                Event::Credit(album, artist, label) => {
+                    self.crawl_links(&album, [artist.clone()]);
+                    self.crawl_links(&album, label.clone());
+                    self.scraped.send(Response::Credit(album, artist.clone()))?;
+                    if let Some(label) = label {
+                        self.scraped.send(Response::Roster(label, vec![artist]))?;
+                    }
+                }
+                Event::Recommended(album, recommended) => {
+                    self.crawl_links(&album, recommended.iter().cloned());
+                    self.scraped.send(Response::Recommended(album, recommended))?;
+                }
+                Event::Fans(album, users) => {
+                    self.crawl_links(&album, users.iter().cloned());
+                    fetched += users.len();
+                    let empty = users.is_empty();
+                    self.scraped.send(Response::Fans(album, users))?;
+                    // Albums without reviews or supporters still send their empty lists, which isn't progress
+                    if !empty {
+                        self.scraped.send(Response::Progress { fetched, expected })?;
+                    }
+                }
+                Event::Collection(user, purchases) => {
+                    self.crawl_links(&user, purchases.iter().map(|purchase| purchase.album.clone()));
+                    fetched += purchases.len();
+                    self.scraped.send(Response::Collection(user, purchases))?;
+                    self.scraped.send(Response::Progress { fetched, expected })?;
+                }
+                Event::Follows(user, artists) => {
+                    self.crawl_links(&user, artists.iter().cloned());
+                    self.scraped.send(Response::Follows(user, artists))?;
+                }
+                Event::Roster(label, artists) => {
+                    self.crawl_links(&label, artists.iter().cloned());
+                    for artist in &artists {
+                        self.scraped.send(Response::Discography(artist.url.clone()))?;
+                    }
+                    self.scraped.send(Response::Roster(label, artists))?;
+                }
+                Event::Signed(artist, label) => {
+                    self.crawl_links(&artist, [label.clone()]);
+                    self.scraped.send(Response::Roster(label, vec![artist]))?;
+                }
+                Event::Release(url) => {
+                    self.crawl(|crawl| crawl.link_release(header.as_ref(), &url));
+                    self.scraped.send(Response::Release(url))?;
+                }
+                Event::Done => {
+                    if let Some(entity) = header.take() {
+                        self.crawl(|crawl| crawl.scraped(entity.url()));
+                        let at = self.finish(requested.clone(), &entity)?;
+                        self.scraper.client().remove_partial(entity.url())?;
+                        self.scraped.send(Response::Scraped(entity, at))?;
+                    }
+                }
+                Event::Partial(cursor) => {
+                    if let Some(entity) = header.take() {
+                        self.crawl(|crawl| crawl.scraped(entity.url()));
+                        let at = self.finish(requested.clone(), &entity)?;
+                        self.scraper.client().save_partial(&cursor)?;
+                        self.scraped.send(Response::Partial(entity, cursor, at))?;
+                    }
+                }
+                Event::Cancelled => {
+                    // Without a cursor there's nothing to continue from, so the entity is left to be scraped again
+                    tracing::info!(entity = ?header, "cancelled before it could be left partially scraped");
                 }
-                tracing::error!(?error, "failed handling scrape request");
             }
         }
     }
 
+    /// Records both the url an entity was requested as and the one it turned out to have, returning when its page was
+    /// fetched
+    ///
+    /// That comes from the web cache, so it stays the same across restarts until the page is fetched again.
+    #[fehler::throws]
+    fn finish(&self, requested: Option<Url>, entity: &EntityData) -> SystemTime {
+        let url = Url::parse(entity.url())?;
+        let client = self.scraper.client();
+        let retrieved = match &requested {
+            Some(requested) => client.retrieved(requested)?,
+            None => None,
+        };
+        let at = match retrieved {
+            Some(at) => at,
+            None => client.retrieved(&url)?.unwrap_or_else(SystemTime::now),
+        };
+        let scraped = Scraped { at, entity: entity.clone() };
+        let mut done = self.done.borrow_mut();
+        if let Some(requested) = requested {
+            done.insert(requested, scraped.clone());
+        }
+        done.insert(url, scraped);
+        at
+    }
+
     #[fehler::throws]
     #[tracing::instrument(skip(self))]
     fn handle_request(&self, request: Request) {
+        #[cfg(test)]
+        if self.panic_on.is_some() && self.panic_on == request.url() {
+            panic!("panicking on request");
+        }
         match request {
             Request::User { url } => {
-                let user = RefCell::new(None);
-                self.scraper.scrape_fan(&Url::parse(&url)?, |fan| {
-                    user.replace(Some(fan));
-                    Ok(())
-                }, |collection| {
-                    self.scraped.send(Response::Collection(user.borrow().clone().unwrap(), collection))?;   
-                    Ok(())
-                })?;
-                self.scraped.send(Response::User(user.replace(None).take().unwrap()))?;
+                let url = match self.claim(&url)? { Some(url) => url, None => return };
+                self.forward(self.scraper.scrape_fan(&url).with_budget(self.config.budget.clone()), Some(url))?;
             }
             Request::Album { url } => {
-                let album = RefCell::new(None);
-                self.scraper.scrape_album(&Url::parse(&url)?, |new_album| {
-                    album.replace(Some(new_album));
-                    Ok(())
-                }, |fans| {
-                    self.scraped.send(Response::Fans(album.borrow().clone().unwrap(), fans))?;
-                    Ok(())
-                })?;
-                self.scraped.send(Response::Album(album.replace(None).take().unwrap()))?;
+                let url = match self.claim(&url)? { Some(url) => url, None => return };
+                self.forward(self.scraper.scrape_album(&url).with_budget(self.config.budget.clone()), Some(url))?;
             }
             Request::Artist { url } => {
-                self.scraper.scrape_artist(&Url::parse(&url)?, |album| {
-                    self.scraped.send(Response::Release(album))?;
-                    Ok(())
-                })?;
+                let url = match self.claim(&url)? { Some(url) => url, None => return };
+                self.forward(self.scraper.scrape_artist(&url).with_budget(self.config.budget.clone()), Some(url))?;
+            }
+            Request::Label { url } => {
+                let url = match self.claim(&url)? { Some(url) => url, None => return };
+                self.forward(self.scraper.scrape_label(&url, self.config.roster_limit).with_budget(self.config.budget.clone()), Some(url))?;
+            }
+            Request::Tag { tag } => {
+                self.forward(self.scraper.scrape_tag(&tag, self.config.tag_limit), None)?;
+            }
+            Request::Continue { cursor } => {
+                self.forward(self.scraper.resume(cursor).with_budget(self.config.budget.clone()), None)?;
+            }
+            Request::Search { query, kind } => {
+                let results = self.scraper.scrape_search(&query, kind)?;
+                if results.is_empty() {
+                    tracing::warn!(%query, ?kind, "no search results");
+                }
+                self.scraped.send(Response::SearchResults(results))?;
             }
+            Request::Cancel { .. } | Request::CancelAll => {}
         }
     }
 }
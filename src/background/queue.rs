@@ -0,0 +1,106 @@
+use std::{cmp::Reverse, collections::{BTreeSet, HashMap}};
+
+/// How urgently a request should be handled, later variants are handled first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, strum::AsRefStr, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Priority {
+    /// Re-scraping an entity that was scraped too long ago
+    Refresh,
+    /// Found while scraping something else, e.g. the releases of a scraped artist
+    Derived,
+    /// Given on the command line
+    Seed,
+    /// Clicked in the ui
+    User,
+}
+
+/// Pending requests, ordered by priority then by when they were queued
+///
+/// Each request is identified by a key, queueing a key that is already pending keeps the original request and only
+/// ever raises its priority.
+#[derive(Debug)]
+pub(crate) struct Queue<T> {
+    order: BTreeSet<(Reverse<Priority>, u64, String)>,
+    pending: HashMap<String, Pending<T>>,
+    queued: u64,
+}
+
+#[derive(Debug)]
+struct Pending<T> {
+    priority: Priority,
+    sequence: u64,
+    item: T,
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self { order: BTreeSet::new(), pending: HashMap::new(), queued: 0 }
+    }
+}
+
+impl<T> Queue<T> {
+    /// Queues an item, returning `false` if one with the same key was already pending
+    pub(crate) fn push(&mut self, key: String, priority: Priority, item: T) -> bool {
+        if self.pending.contains_key(&key) {
+            self.reprioritise(&key, priority);
+            return false;
+        }
+        let sequence = self.next_sequence();
+        self.order.insert((Reverse(priority), sequence, key.clone()));
+        self.pending.insert(key, Pending { priority, sequence, item });
+        true
+    }
+
+    /// Raises the priority of a pending item, moving it behind the items already queued at that priority
+    ///
+    /// Returns `false` if nothing is pending with that key or it already has at least that priority.
+    pub(crate) fn reprioritise(&mut self, key: &str, priority: Priority) -> bool {
+        let sequence = self.next_sequence();
+        let Some(pending) = self.pending.get_mut(key) else { return false };
+        if pending.priority >= priority {
+            return false;
+        }
+        let mut entry = self.order.take(&(Reverse(pending.priority), pending.sequence, key.to_owned())).expect("pending items are ordered");
+        entry.0 = Reverse(priority);
+        entry.1 = sequence;
+        pending.priority = priority;
+        pending.sequence = sequence;
+        self.order.insert(entry);
+        true
+    }
+
+    /// Takes the most urgent item, along with its key and priority
+    pub(crate) fn pop(&mut self) -> Option<(String, Priority, T)> {
+        let (Reverse(priority), _, key) = self.order.pop_first()?;
+        let item = self.pending.remove(&key).expect("ordered items are pending").item;
+        Some((key, priority, item))
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<T> {
+        let pending = self.pending.remove(key)?;
+        self.order.remove(&(Reverse(pending.priority), pending.sequence, key.to_owned()));
+        Some(pending.item)
+    }
+
+    /// Empties the queue, returning every item that was pending along with its key
+    pub(crate) fn clear(&mut self) -> Vec<(String, T)> {
+        self.order.clear();
+        self.pending.drain().map(|(key, pending)| (key, pending.item)).collect()
+    }
+
+    pub(crate) fn priority(&self, key: &str) -> Option<Priority> {
+        self.pending.get(key).map(|pending| pending.priority)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        self.queued += 1;
+        self.queued
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,69 @@
+use super::{Priority, Queue};
+
+fn drain(queue: &mut Queue<&'static str>) -> Vec<&'static str> {
+    std::iter::from_fn(|| queue.pop().map(|(_, _, item)| item)).collect()
+}
+
+#[test]
+fn higher_priorities_first_then_in_queued_order() {
+    let mut queue = Queue::default();
+    queue.push("a".to_owned(), Priority::Derived, "a");
+    queue.push("b".to_owned(), Priority::Seed, "b");
+    queue.push("c".to_owned(), Priority::Derived, "c");
+    queue.push("d".to_owned(), Priority::User, "d");
+    queue.push("e".to_owned(), Priority::Seed, "e");
+    queue.push("f".to_owned(), Priority::Refresh, "f");
+
+    assert_eq!(drain(&mut queue), ["d", "b", "e", "a", "c", "f"]);
+}
+
+#[test]
+fn duplicates_keep_the_first_request_at_the_highest_priority() {
+    let mut queue = Queue::default();
+    assert!(queue.push("a".to_owned(), Priority::Derived, "a"));
+    assert!(queue.push("b".to_owned(), Priority::Seed, "b"));
+    assert!(!queue.push("a".to_owned(), Priority::User, "a again"));
+    assert!(!queue.push("b".to_owned(), Priority::Derived, "b again"));
+
+    assert_eq!(queue.len(), 2);
+    assert_eq!(drain(&mut queue), ["a", "b"]);
+}
+
+#[test]
+fn reprioritised_items_go_behind_those_already_at_that_priority() {
+    let mut queue = Queue::default();
+    queue.push("a".to_owned(), Priority::Derived, "a");
+    queue.push("b".to_owned(), Priority::Seed, "b");
+    queue.push("c".to_owned(), Priority::Derived, "c");
+
+    assert!(queue.reprioritise("c", Priority::Seed));
+    assert!(!queue.reprioritise("b", Priority::Derived));
+    assert!(!queue.reprioritise("missing", Priority::User));
+
+    assert_eq!(drain(&mut queue), ["b", "c", "a"]);
+}
+
+#[test]
+fn removed_items_are_never_popped() {
+    let mut queue = Queue::default();
+    queue.push("a".to_owned(), Priority::Seed, "a");
+    queue.push("b".to_owned(), Priority::Seed, "b");
+
+    assert_eq!(queue.remove("a"), Some("a"));
+    assert_eq!(queue.remove("a"), None);
+    assert_eq!(queue.pop(), Some(("b".to_owned(), Priority::Seed, "b")));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn clearing_returns_every_pending_item() {
+    let mut queue = Queue::default();
+    queue.push("a".to_owned(), Priority::Seed, "a");
+    queue.push("b".to_owned(), Priority::Derived, "b");
+
+    let mut cleared = queue.clear();
+    cleared.sort();
+    assert_eq!(cleared, [("a".to_owned(), "a"), ("b".to_owned(), "b")]);
+    assert_eq!(queue.len(), 0);
+    assert_eq!(queue.pop(), None);
+}
@@ -1,58 +1,262 @@
 use url::Url;
 use eyre::{Error, Result};
-use std::collections::HashMap;
-use opt::data::{User, Album, UserId, AlbumId};
+use std::{collections::{HashMap, HashSet}, time::SystemTime};
+use opt::data::{User, Album, AlbumInfo, Artist, Label, Purchase, EntityData, UserId, AlbumId, ArtistId, LabelId, ItemKind};
+use super::{drift::Drift, error::{ErrorKind, ScrapeError}, event::{Cursor, Event, Events, Fetched, Step}, web::Page, SearchKind, SearchResult};
 
 #[derive(Debug)]
 pub(crate) struct Scraper {
     client: super::web::Client,
+    drift: Drift,
 }
 
-trait JsonExt {
-    #[fehler::throws]
-    fn parse_json<T: serde::de::DeserializeOwned>(&self) -> T;
+#[fehler::throws]
+fn selector(selector: &str) -> scraper::Selector {
+    scraper::Selector::parse(selector).map_err(|e| eyre::eyre!("{e:?}"))?
+}
+
+/// A parsed html page, failures to find things in it are reported with the page's context
+struct Document<'a> {
+    page: &'a Page,
+    drift: &'a Drift,
+    html: scraper::Html,
 }
 
-impl JsonExt for str {
+impl<'a> Document<'a> {
+    fn parse(page: &'a Page, drift: &'a Drift) -> Self {
+        Self { page, drift, html: scraper::Html::parse_document(&page.body) }
+    }
+
     #[fehler::throws]
-    fn parse_json<T: serde::de::DeserializeOwned>(&self) -> T {
-        serde_json::from_str(self)?
+    #[tracing::instrument(skip(self))]
+    fn select(&self, selector: &str) -> Vec<scraper::ElementRef<'_>> {
+        self.html.select(&self::selector(selector)?).collect()
     }
-}
 
-trait DocumentExt {
     #[fehler::throws]
-    fn try_select(&self, selector: &str) -> Vec<scraper::ElementRef<'_>>;
+    #[tracing::instrument(skip(self))]
+    fn select_one(&self, selector: &str) -> scraper::ElementRef<'_> {
+        self.select_one_in(self.html.root_element(), selector)?
+    }
 
     #[fehler::throws]
-    fn try_select_one(&self, selector: &str) -> scraper::ElementRef<'_>;
-}
+    #[tracing::instrument(skip(self, element))]
+    fn select_one_in<'b>(&self, element: scraper::ElementRef<'b>, selector: &str) -> scraper::ElementRef<'b> {
+        element.select(&self::selector(selector)?).next().ok_or_else(|| self.error(ErrorKind::MissingSelector { selector: selector.to_owned() }, element))?
+    }
 
-impl DocumentExt for scraper::Html {
     #[fehler::throws]
-    #[tracing::instrument(skip(self))]
-    fn try_select(&self, selector: &str) -> Vec<scraper::ElementRef<'_>> {
-        let s = scraper::Selector::parse(selector).map_err(|e| eyre::eyre!("{e:?}"))?;
-        self.select(&s).collect()
+    fn attr<'b>(&self, element: scraper::ElementRef<'b>, attribute: &str) -> &'b str {
+        element.value().attr(attribute).ok_or_else(|| self.error(ErrorKind::MissingAttribute {
+            element: element.value().name().to_owned(),
+            attribute: attribute.to_owned(),
+        }, element))?
     }
 
+    /// Parses an attribute like an id, failing the page if it doesn't hold what we expect
     #[fehler::throws]
-    #[tracing::instrument(skip(self))]
-    fn try_select_one(&self, selector: &str) -> scraper::ElementRef<'_> {
-        let s = scraper::Selector::parse(selector).map_err(|e| eyre::eyre!("{e:?}"))?;
-        self.select(&s).next().ok_or_else(|| eyre::eyre!("missing element for {selector}"))?
+    fn parse_attr<T: std::str::FromStr>(&self, element: scraper::ElementRef<'_>, attribute: &str) -> T {
+        let value = self.attr(element, attribute)?;
+        value.parse().map_err(|_| self.error(ErrorKind::InvalidAttribute {
+            element: element.value().name().to_owned(),
+            attribute: attribute.to_owned(),
+            value: value.to_owned(),
+        }, element))?
+    }
+
+    /// An error with a snippet around `element`, or around the start of the body when searching the whole page
+    fn error(&self, kind: ErrorKind, element: scraper::ElementRef<'_>) -> ScrapeError {
+        let offset = (element.id() != self.html.root_element().id()).then(|| self.offset_of(element)).flatten();
+        self.page.error_at(kind, offset)
+    }
+
+    /// Where `element`'s start tag is in the page source, found by counting the elements with the same name before it
+    ///
+    /// The parsed tree doesn't keep source positions, so this is thrown off by elements the parser inserts or tags
+    /// inside scripts, which only makes the snippet less useful.
+    fn offset_of(&self, element: scraper::ElementRef<'_>) -> Option<usize> {
+        let name = element.value().name();
+        let index = self.html.root_element().descendants().filter_map(scraper::ElementRef::wrap)
+            .filter(|other| other.value().name() == name)
+            .position(|other| other.id() == element.id())?;
+        let body = self.page.body.to_ascii_lowercase();
+        let tag = format!("<{name}");
+        body.match_indices(&tag)
+            .filter(|(start, _)| body[start + tag.len()..].starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/'))
+            .nth(index)
+            .map(|(start, _)| start)
+    }
+
+    #[fehler::throws]
+    fn json<T: serde::de::DeserializeOwned>(&self, text: &str) -> T {
+        self.page.parse_json(text, self.drift)?
+    }
+
+    /// The url the page declares for itself in its canonical link or `og:url`
+    #[fehler::throws]
+    fn canonical_url(&self) -> Option<Url> {
+        let declared = self.select("link[rel=canonical]")?.into_iter().find_map(|link| link.value().attr("href"))
+            .or_else(|| self.select("meta[property=\"og:url\"]").ok()?.into_iter().find_map(|meta| meta.value().attr("content")));
+        match declared {
+            Some(declared) => Some(canonicalize(&self.page.url.join(declared)?)),
+            None => None,
+        }
     }
 }
 
+/// Strips the parts of an entity url that don't identify it, like the `?from=...` tracking suffix on links
+pub(crate) fn canonicalize(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    url
+}
+
 #[derive(Debug)]
 struct AlbumPage {
+    url: Url,
     properties: Properties,
     collectors: Collectors,
+    /// `None` when the ld+json names neither the artist nor the publisher's account
+    credits: Option<(Artist, Option<Label>)>,
+    /// Releases from the "you may also like" footer
+    recommendations: Vec<Album>,
 }
 
 #[derive(Debug, serde::Deserialize)]
-struct Properties {
+struct LdJson {
+    #[serde(rename = "byArtist")]
+    by_artist: Option<Band>,
+    publisher: Option<Band>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Band {
+    #[serde(rename = "@id")]
+    url: Option<String>,
+    #[serde(default, rename = "additionalProperty")]
+    additional_property: Vec<PropertyValue>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PropertyValue {
+    name: String,
+    value: serde_json::Value,
+}
+
+impl Band {
+    fn band_id(&self) -> Option<u64> {
+        self.additional_property.iter().find(|p| p.name == "band_id")?.value.as_u64()
+    }
+
+    /// The account's id and site root, if the ld+json links it rather than just naming it
+    fn account(&self) -> Option<(u64, String)> {
+        Some((self.band_id()?, band_root(self.url.as_deref()?).ok()?))
+    }
+}
+
+impl LdJson {
+    /// The artist credited with the release, and the label publishing it if that is a different account
+    ///
+    /// Self-released albums often only name the artist, in which case the publisher is the artist's own account.
+    fn credits(&self) -> Option<(Artist, Option<Label>)> {
+        let artist = self.by_artist.as_ref().and_then(Band::account);
+        let publisher = self.publisher.as_ref().and_then(Band::account);
+        match (artist, publisher) {
+            (Some((id, url)), Some((publisher_id, publisher_url))) if id != publisher_id => Some((
+                Artist { id: ArtistId(id), url },
+                Some(Label { id: LabelId(publisher_id), url: publisher_url }),
+            )),
+            (_, Some((id, url))) | (Some((id, url)), None) => Some((Artist { id: ArtistId(id), url }, None)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The root of an artist or label's site, which is how they are identified in rosters and requests
+#[fehler::throws]
+fn band_root(url: &str) -> String {
+    Url::parse(url)?.join("/")?.to_string()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BandData {
+    id: u64,
+}
+
+#[derive(Debug)]
+struct BandPage {
+    /// Where the page was finally retrieved from, which may be a custom domain
+    url: Url,
+    band: BandData,
+    /// `None` when the page has no discography grid, e.g. an artist root showing a featured release
+    releases: Option<Vec<String>>,
+    /// The root of the label an artist is signed to, from the "back to label" link in the header
+    label: Option<Url>,
+}
+
+/// Grid entries past the first page are rendered client side from this json
+#[derive(Debug, serde::Deserialize)]
+struct ClientItem {
+    page_url: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Discover {
+    results: Vec<DiscoverItem>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DiscoverItem {
+    item_url: String,
+}
+
+/// The `data-search` attribute of a search result
+#[derive(Debug, serde::Deserialize)]
+struct SearchItem {
+    #[serde(rename = "type")]
     item_type: String,
+    id: u64,
+    #[serde(default)]
+    is_header: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RosterItem {
+    band_id: u64,
+    url: String,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+enum TralbumType {
+    #[serde(rename = "a")]
+    Album,
+    #[serde(rename = "t")]
+    Track,
+}
+
+impl From<ItemKind> for TralbumType {
+    fn from(kind: ItemKind) -> Self {
+        match kind {
+            ItemKind::Album => TralbumType::Album,
+            ItemKind::Track => TralbumType::Track,
+        }
+    }
+}
+
+impl From<TralbumType> for ItemKind {
+    fn from(ty: TralbumType) -> Self {
+        match ty {
+            TralbumType::Album => ItemKind::Album,
+            TralbumType::Track => ItemKind::Track,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Properties {
+    item_type: TralbumType,
     item_id: u64,
 }
 
@@ -88,11 +292,84 @@ struct Thumbs {
 pub struct CollectionItem {
     item_id: u64,
     item_url: String,
+    tralbum_type: TralbumType,
+    #[serde(deserialize_with = "deserialize_date")]
+    added: SystemTime,
+    band_name: String,
+    item_title: String,
+    #[serde(default)]
+    genre_id: Option<u32>,
+    #[serde(default)]
+    featured_track: Option<u64>,
+    #[serde(default)]
+    featured_track_title: Option<String>,
+}
+
+impl CollectionItem {
+    fn into_purchase(self) -> Purchase {
+        Purchase {
+            album: Album { id: AlbumId(self.tralbum_type.into(), self.item_id), url: self.item_url },
+            added: self.added,
+            info: AlbumInfo {
+                title: self.item_title,
+                artist: self.band_name,
+                genre_id: self.genre_id,
+                featured_track: self.featured_track.zip(self.featured_track_title),
+            },
+        }
+    }
+}
+
+/// Collection timestamps are rendered like `01 Jan 2023 10:00:00 GMT`
+fn deserialize_date<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+    let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+    let date = chrono::NaiveDateTime::parse_from_str(&text, "%d %b %Y %H:%M:%S GMT").map_err(serde::de::Error::custom)?;
+    Ok(date.and_utc().into())
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct ItemCache {
     collection: HashMap<String, CollectionItem>,
+    #[serde(default)]
+    following_bands: HashMap<String, FollowedBand>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FollowingData {
+    sequence: Vec<String>,
+    last_token: Option<String>,
+    batch_size: usize,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FollowedBand {
+    band_id: u64,
+    url_hints: UrlHints,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct UrlHints {
+    subdomain: String,
+    custom_domain: Option<String>,
+}
+
+impl FollowedBand {
+    /// Followed labels can't be told apart from artists here, the graph follows those it knows as labels
+    #[fehler::throws]
+    fn into_artist(self) -> Artist {
+        let url = match self.url_hints.custom_domain {
+            Some(domain) => format!("https://{domain}/"),
+            None => format!("https://{}.bandcamp.com/", self.url_hints.subdomain),
+        };
+        Artist { id: ArtistId(self.band_id), url: Url::parse(&url)?.to_string() }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Following {
+    followeebands: Vec<FollowedBand>,
+    more_available: bool,
+    last_token: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -113,6 +390,8 @@ struct FanPage {
     collection_count: usize,
     collection_data: CollectionData,
     item_cache: ItemCache,
+    #[serde(default)]
+    following_bands_data: Option<FollowingData>,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -122,109 +401,466 @@ struct Collections {
     items: Vec<CollectionItem>,
 }
 
+#[fehler::throws]
+fn parse_band_page(page: &Page, drift: &Drift) -> BandPage {
+    let url = page.url.clone();
+    let document = Document::parse(page, drift);
+    let band = document.json(document.attr(document.select_one("script[data-band]")?, "data-band")?)?;
+
+    let grid = document.select("li.music-grid-item a")?;
+    let client_items = document.select("ol[data-client-items]")?;
+    let releases = if grid.is_empty() && client_items.is_empty() {
+        None
+    } else {
+        let mut releases = Vec::new();
+        for a in grid {
+            releases.push(document.attr(a, "href")?.to_owned());
+        }
+        for ol in client_items {
+            let items: Vec<ClientItem> = document.json(document.attr(ol, "data-client-items")?)?;
+            releases.extend(items.into_iter().map(|item| item.page_url));
+        }
+        let mut seen = HashSet::new();
+        let mut urls = Vec::new();
+        for href in releases {
+            let mut release = url.join(&href)?;
+            // Grid links on label pages carry `?label=...&tab=music`, which would otherwise defeat deduplication
+            release.set_query(None);
+            if seen.insert(release.clone()) {
+                urls.push(release.to_string());
+            }
+        }
+        Some(urls)
+    };
+    let label = match document.select("a.back-to-label-link[href]")?.first() {
+        Some(a) => Some(url.join(document.attr(*a, "href")?)?.join("/")?),
+        None => None,
+    };
+
+    BandPage {
+        url,
+        band,
+        releases,
+        label,
+    }
+}
+
+/// Fans are only listed by username, their fan pages live under it
+fn user(fan_id: u64, username: &str) -> User {
+    User { id: UserId(fan_id), url: format!("https://bandcamp.com/{username}") }
+}
+
 impl Scraper {
-    pub(crate) fn new(client: super::web::Client) -> Self {
-        Self { client }
+    pub(crate) fn new(client: super::web::Client, drift: Drift) -> Self {
+        Self { client, drift }
+    }
+
+    pub(crate) fn drift(&self) -> &Drift {
+        &self.drift
+    }
+
+    pub(crate) fn client(&self) -> &super::web::Client {
+        &self.client
     }
 
+    /// The canonical url of an entity, as far as is known without fetching it
     #[fehler::throws]
-    #[tracing::instrument(skip(self, on_album, on_fans), fields(%url))]
-    pub(crate) fn scrape_album(&self, url: &Url, on_album: impl FnOnce(Album) -> Result<()> , mut on_fans: impl FnMut(Vec<User>) -> Result<()>) {
-        let page = self.scrape_album_page(url)?;
+    pub(crate) fn resolve(&self, url: &Url) -> Url {
+        let url = canonicalize(url);
+        self.client.canonical(&url)?.unwrap_or(url)
+    }
+
+    #[fehler::throws]
+    fn alias(&self, requested: &Url, canonical: &Url) {
+        if requested != canonical {
+            self.client.add_alias(requested, canonical)?;
+        }
+    }
 
-        let mut more_available = page.collectors.more_thumbs_available;
-        on_album(Album {
-            id: AlbumId(page.properties.item_id),
-            url: url.to_string(),
-        })?;
+    pub(crate) fn scrape_album(&self, url: &Url) -> Events<'_> {
+        let url = url.clone();
+        Events::new(Step::new(move || {
+            let _span = tracing::info_span!("scrape_album", %url).entered();
+            let page = self.scrape_album_page(&url)?;
+            let album = Album {
+                id: AlbumId(page.properties.item_type.into(), page.properties.item_id),
+                url: page.url.to_string(),
+            };
+            let reviews = page.collectors.reviews.into_iter().map(|review| user(review.fan_id, &review.username)).collect();
+            let token = page.collectors.thumbs.last().map(|thumb| thumb.token.clone());
+            let thumbs = page.collectors.thumbs.into_iter().map(|thumb| user(thumb.fan_id, &thumb.username)).collect();
+            let mut events = vec![Event::Header(album.clone().into())];
+            events.extend(page.credits.map(|(artist, label)| Event::Credit(album.clone(), artist, label)));
+            events.extend([
+                Event::Recommended(album.clone(), page.recommendations),
+                Event::Fans(album.clone(), reviews),
+                Event::Fans(album.clone(), thumbs),
+            ]);
+            Ok(match token {
+                Some(token) if page.collectors.more_thumbs_available => Fetched::then(events, self.collectors_step(album, token)),
+                _ => Fetched::last(events),
+            })
+        }))
+    }
+
+    /// Picks a crawl back up where a budget cut it off
+    pub(crate) fn resume(&self, cursor: Cursor) -> Events<'_> {
+        let header = Event::Header(cursor.entity());
+        let next = match cursor {
+            Cursor::Fans { album, token } => self.collectors_step(album, token),
+            Cursor::Collection { user, token, following } => self.collection_step(user, token, following),
+            Cursor::Following { user, token } => self.following_step(user, token),
+        };
+        Events::resume(vec![header], next)
+    }
+
+    fn collectors_step(&self, album: Album, token: String) -> Step<'_> {
+        Step::resumable(Cursor::Fans { album: album.clone(), token: token.clone() }, move || {
+            let _span = tracing::info_span!("scrape_album", url = %album.url).entered();
+            let (api, response) = self.scrape_collectors_api(&album, &token)?;
+            // Only a page with more after it needs to say where to carry on from, the last one may well be empty
+            let token = match response.results.last() {
+                Some(thumb) => Some(thumb.token.clone()),
+                None if response.more_available => Err(api.error(ErrorKind::Pagination { reason: "more thumbs available after an empty page".to_owned() }))?,
+                None => None,
+            };
+            let fans = response.results.into_iter().map(|thumb| user(thumb.fan_id, &thumb.username)).collect();
+            let events = vec![Event::Fans(album.clone(), fans)];
+            Ok(match token {
+                Some(token) if response.more_available => Fetched::then(events, self.collectors_step(album, token)),
+                _ => Fetched::last(events),
+            })
+        })
+    }
 
-        let token = page.collectors.thumbs.last().map(|thumb| thumb.token.clone());
-        on_fans(page.collectors.reviews.into_iter().map(|review| User { id: UserId(review.fan_id), url: format!("https://bandcamp.com/{}", review.username), }).collect())?;
-        on_fans(page.collectors.thumbs.into_iter().map(|thumb| User { id: UserId(thumb.fan_id), url: format!("https://bandcamp.com/{}", thumb.username), }).collect())?;
+    pub(crate) fn scrape_fan(&self, url: &Url) -> Events<'_> {
+        let url = url.clone();
+        Events::new(Step::new(move || {
+            let _span = tracing::info_span!("scrape_fan", %url).entered();
+            let (html, canonical, mut page) = self.scrape_fan_page(&url)?;
+            let user = User { id: UserId(page.fan_data.fan_id), url: canonical.to_string() };
 
-        if let Some(mut token) = token {
-            while more_available {
-                let response = self.scrape_collectors_api(url, &page.properties, &token)?;
-                token = response.results.last().unwrap().token.clone();
-                more_available = response.more_available;
-                on_fans(response.results.into_iter().map(|thumb| User { id: UserId(thumb.fan_id), url: format!("https://bandcamp.com/{}", thumb.username), }).collect())?;
+            let mut items = Vec::new();
+            for s in page.collection_data.sequence {
+                items.push(page.item_cache.collection.remove(&s).ok_or_else(|| html.error(ErrorKind::MissingItem { list: "collection sequence", key: s.clone() }))?);
             }
-        }
+            let more_available = items.len() < page.collection_count;
+            let mut events = vec![
+                Event::Header(user.clone().into()),
+                Event::Expected(page.collection_count),
+                Event::Collection(user.clone(), items.into_iter().map(CollectionItem::into_purchase).collect()),
+            ];
+
+            let mut following = None;
+            if let Some(data) = page.following_bands_data {
+                let mut bands = Vec::new();
+                for s in &data.sequence {
+                    bands.push(page.item_cache.following_bands.remove(s).ok_or_else(|| html.error(ErrorKind::MissingItem { list: "following sequence", key: s.clone() }))?.into_artist()?);
+                }
+                // There's no count of followed bands, a full first batch is the only hint there may be more
+                if data.sequence.len() >= data.batch_size {
+                    following = data.last_token;
+                }
+                events.push(Event::Follows(user.clone(), bands));
+            }
+
+            Ok(match (more_available, following) {
+                (true, following) => Fetched::then(events, self.collection_step(user, page.collection_data.last_token, following)),
+                (false, Some(following)) => Fetched::then(events, self.following_step(user, following)),
+                (false, None) => Fetched::last(events),
+            })
+        }))
     }
 
-    #[fehler::throws]
-    #[tracing::instrument(skip(self, on_fan, on_collection))]
-    pub(crate) fn scrape_fan(&self, url: &Url, on_fan: impl FnOnce(User) -> Result<()>, mut on_collection: impl FnMut(Vec<Album>) -> Result<()>) {
-        let mut page = self.scrape_fan_page(url)?;
+    /// Pages through the rest of a collection, then the rest of the followed bands if `following` is set
+    fn collection_step(&self, user: User, token: String, following: Option<String>) -> Step<'_> {
+        Step::resumable(Cursor::Collection { user: user.clone(), token: token.clone(), following: following.clone() }, move || {
+            let _span = tracing::info_span!("scrape_fan", url = %user.url).entered();
+            let (_, response) = self.scrape_collections_api(user.id.0, &token)?;
+            let events = vec![Event::Collection(user.clone(), response.items.into_iter().map(CollectionItem::into_purchase).collect())];
+            Ok(match (response.more_available, following) {
+                (true, following) => Fetched::then(events, self.collection_step(user, response.last_token, following)),
+                (false, Some(following)) => Fetched::then(events, self.following_step(user, following)),
+                (false, None) => Fetched::last(events),
+            })
+        })
+    }
 
-        on_fan(User { id: UserId(page.fan_data.fan_id), url: format!("https://bandcamp.com/{}", page.fan_data.username) })?;
+    fn following_step(&self, user: User, token: String) -> Step<'_> {
+        Step::resumable(Cursor::Following { user: user.clone(), token: token.clone() }, move || {
+            let _span = tracing::info_span!("scrape_fan", url = %user.url).entered();
+            let (_, response) = self.scrape_following_api(user.id.0, &token)?;
+            let bands = response.followeebands.into_iter().map(FollowedBand::into_artist).collect::<Result<_>>()?;
+            let events = vec![Event::Follows(user.clone(), bands)];
+            Ok(match (response.more_available, response.last_token) {
+                (true, Some(token)) => Fetched::then(events, self.following_step(user, token)),
+                _ => Fetched::last(events),
+            })
+        })
+    }
 
-        let items = Result::<Vec<_>, _>::from_iter(page.collection_data.sequence.into_iter().map(|s| page.item_cache.collection.remove(&s).ok_or_else(|| eyre::eyre!("cache missing collection item"))))?;
-        let mut last_token = page.collection_data.last_token;
-        let mut more_available = items.len() < page.collection_count;
-        on_collection(items.into_iter().map(|item| Album { id: AlbumId(item.item_id), url: item.item_url }).collect())?;
+    pub(crate) fn scrape_artist(&self, url: &Url) -> Events<'_> {
+        let url = url.clone();
+        Events::new(Step::new(move || {
+            let _span = tracing::info_span!("scrape_artist", %url).entered();
+            let page = self.scrape_band_page(&url)?;
+            let root = page.url.join("/")?;
+            self.alias(&url, &root)?;
+
+            let artist = Artist { id: ArtistId(page.band.id), url: root.to_string() };
+            let mut events = vec![Event::Header(artist.clone().into())];
+            events.extend(page.releases.unwrap_or_default().into_iter().map(Event::Release));
+            Ok(match page.label {
+                // The artist page only links the label, its id is on the label's own page
+                Some(label) => Fetched::then(events, Step::new(move || {
+                    let _span = tracing::info_span!("scrape_artist", url = %artist.url).entered();
+                    let label_page = parse_band_page(&self.client.get_page(&label)?, &self.drift)?;
+                    let label = Label { id: LabelId(label_page.band.id), url: label.to_string() };
+                    Ok(Fetched::last(vec![Event::Signed(artist, label)]))
+                })),
+                None => Fetched::last(events),
+            })
+        }))
+    }
 
-        while more_available {
-            let response = self.scrape_collections_api(page.fan_data.fan_id, &last_token)?;
-            more_available = response.more_available;
-            last_token = response.last_token;
-            on_collection(response.items.into_iter().map(|item| Album { id: AlbumId(item.item_id), url: item.item_url }).collect())?;
-        }
+    pub(crate) fn scrape_label(&self, url: &Url, roster_limit: usize) -> Events<'_> {
+        let url = url.clone();
+        Events::new(Step::new(move || {
+            let _span = tracing::info_span!("scrape_label", %url).entered();
+            let page = self.scrape_band_page(&url)?;
+            let root = page.url.join("/")?;
+            self.alias(&url, &root)?;
+
+            let label = Label { id: LabelId(page.band.id), url: root.to_string() };
+            let releases = page.releases.unwrap_or_default();
+            Ok(Fetched::then(vec![Event::Header(label.clone().into())], Step::new(move || {
+                let _span = tracing::info_span!("scrape_label", url = %root).entered();
+                let roster = self.scrape_roster_page(&root.join("/artists")?)?.unwrap_or_default();
+                if roster.len() > roster_limit {
+                    tracing::info!(total = roster.len(), roster_limit, "truncating label roster");
+                }
+                let roster = roster.into_iter().take(roster_limit).map(|item| Artist { id: ArtistId(item.band_id), url: item.url }).collect();
+                let mut events = vec![Event::Roster(label, roster)];
+                events.extend(releases.into_iter().map(Event::Release));
+                Ok(Fetched::last(events))
+            })))
+        }))
     }
 
-    #[fehler::throws]
-    #[tracing::instrument(skip(self, on_release))]
-    pub(crate) fn scrape_artist(&self, url: &Url, mut on_release: impl FnMut(String) -> Result<()>) {
-        let data = self.client.get(url)?;
-        let document = scraper::Html::parse_document(&data);
+    pub(crate) fn scrape_tag(&self, tag: &str, limit: usize) -> Events<'_> {
+        let tag = tag.to_lowercase().split_whitespace().collect::<Vec<_>>().join("-");
+        Events::new(self.discover_step(tag, limit, "*".to_owned()))
+    }
 
-        for a in document.try_select("li.music-grid-item a")? {
-            let href = a.value().attr("href").ok_or_else(|| eyre::eyre!("missing href"))?;
-            on_release(url.join(href)?.to_string())?;
+    fn discover_step(&self, tag: String, remaining: usize, cursor: String) -> Step<'_> {
+        Step::new(move || {
+            let _span = tracing::info_span!("scrape_tag", %tag, %cursor).entered();
+            if remaining == 0 {
+                return Ok(Fetched::last(Vec::new()));
+            }
+            let (_, response) = self.scrape_discover_api(&tag, &cursor)?;
+            let found = response.results.len().min(remaining);
+            let events = response.results.into_iter().take(remaining).map(|item| Event::Release(item.item_url)).collect();
+            Ok(match response.cursor {
+                Some(next) if found > 0 && remaining > found => Fetched::then(events, self.discover_step(tag, remaining - found, next)),
+                _ => Fetched::last(events),
+            })
+        })
+    }
+
+    /// Returns the first page of results, skipping kinds of items that aren't part of the graph like merch
+    #[fehler::throws]
+    #[tracing::instrument(skip(self))]
+    pub(crate) fn scrape_search(&self, query: &str, kind: SearchKind) -> Vec<SearchResult> {
+        let item_type = match kind {
+            SearchKind::All => "",
+            SearchKind::Albums => "a",
+            SearchKind::Tracks => "t",
+            SearchKind::Artists => "b",
+            SearchKind::Fans => "f",
+        };
+        let mut url = Url::parse("https://bandcamp.com/search")?;
+        url.query_pairs_mut().append_pair("q", query).append_pair("item_type", item_type);
+        let page = self.client.get_page(&url)?;
+        let document = Document::parse(&page, &self.drift);
+
+        let mut results = Vec::new();
+        for li in document.select("li.searchresult[data-search]")? {
+            let item: SearchItem = document.json(document.attr(li, "data-search")?)?;
+            if item.is_header {
+                continue;
+            }
+            let heading = document.select_one_in(li, ".heading a")?;
+            let name = heading.text().collect::<String>().trim().to_owned();
+            let url = canonicalize(&page.url.join(document.attr(heading, "href")?)?).to_string();
+            let entity = match item.item_type.as_str() {
+                "a" => EntityData::Album(Album { id: AlbumId(ItemKind::Album, item.id), url }),
+                "t" => EntityData::Album(Album { id: AlbumId(ItemKind::Track, item.id), url }),
+                "f" => EntityData::User(User { id: UserId(item.id), url }),
+                "b" => {
+                    // Artists and labels share a result type, only the rendered label tells them apart
+                    let item_type = document.select_one_in(li, ".itemtype")?.text().collect::<String>();
+                    if item_type.trim().eq_ignore_ascii_case("label") {
+                        EntityData::Label(Label { id: LabelId(item.id), url })
+                    } else {
+                        EntityData::Artist(Artist { id: ArtistId(item.id), url })
+                    }
+                }
+                other => {
+                    tracing::debug!(item_type = other, %name, "skipping search result");
+                    continue;
+                }
+            };
+            results.push(SearchResult { name, entity });
         }
+        results
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url))]
     fn scrape_album_page(&self, url: &Url) -> AlbumPage {
-        let data = self.client.get(url)?;
-        let document = scraper::Html::parse_document(&data);
-        let properties = document.try_select_one("meta[name=bc-page-properties]")?.value().attr("content").ok_or_else(|| eyre::eyre!("missing data-blob"))?.parse_json()?;
-        let collectors = document.try_select_one("#collectors-data")?.value().attr("data-blob").ok_or_else(|| eyre::eyre!("missing data-blob"))?.parse_json()?;
+        let page = self.client.get_page(url)?;
+        let document = Document::parse(&page, &self.drift);
+        let properties = document.json(document.attr(document.select_one("meta[name=bc-page-properties]")?, "content")?)?;
+        let collectors = document.json(document.attr(document.select_one("#collectors-data")?, "data-blob")?)?;
+        let credits = match document.select("script[type=\"application/ld+json\"]")?.first() {
+            Some(script) => document.json::<LdJson>(&script.inner_html())?.credits(),
+            None => None,
+        };
+        if credits.is_none() {
+            tracing::warn!("album has no linked artist or publisher");
+        }
+        let canonical = document.canonical_url()?.unwrap_or_else(|| canonicalize(&page.url));
+        self.alias(url, &canonical)?;
+        let mut recommendations = Vec::new();
+        for li in document.select("li.recommended-album[data-albumid]")? {
+            let id = document.parse_attr(li, "data-albumid")?;
+            let link = page.url.join(document.attr(document.select_one_in(li, "a.album-link")?, "href")?)?;
+            recommendations.push(Album { id: AlbumId(ItemKind::Album, id), url: canonicalize(&link).to_string() });
+        }
         AlbumPage {
+            url: canonical,
             properties,
             collectors,
+            credits,
+            recommendations,
         }
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url))]
-    fn scrape_fan_page(&self, url: &Url) -> FanPage {
-        let data = self.client.get(url)?;
-        let document = scraper::Html::parse_document(&data);
-        document.try_select_one("#pagedata")?.value().attr("data-blob").ok_or_else(|| eyre::eyre!("missing data-blob"))?.parse_json()?
+    fn scrape_band_page(&self, url: &Url) -> BandPage {
+        let page = self.client.get_page(url)?;
+        let mut band_page = parse_band_page(&page, &self.drift)?;
+        if band_page.releases.is_none() {
+            if band_page.url.path() != "/music" {
+                // Artists with a featured release show it on their root, the full grid is only on `/music`
+                let music = self.client.get_page(&band_page.url.join("/music")?)?;
+                band_page = parse_band_page(&music, &self.drift)?;
+            }
+            if band_page.releases.is_none() {
+                // Artists with a single release have `/music` redirect straight to it
+                let page = self.client.get_page(&band_page.url)?;
+                if Document::parse(&page, &self.drift).select("meta[name=bc-page-properties]")?.len() == 1 {
+                    band_page.releases = Some(vec![band_page.url.to_string()]);
+                } else {
+                    tracing::warn!(url = %band_page.url, "no discography found");
+                }
+            }
+        }
+        band_page
     }
 
     #[fehler::throws]
-    #[tracing::instrument(skip(self), fields(%base_url))]
-    fn scrape_collectors_api(&self, base_url: &Url, props: &Properties, token: &str) -> Thumbs {
-        let url = base_url.join("/api/tralbumcollectors/2/thumbs")?;
-        self.client.post(&url, &serde_json::json!({
-            "tralbum_type": props.item_type,
-            "tralbum_id": props.item_id,
+    #[tracing::instrument(skip(self), fields(%url))]
+    /// Returns `None` for accounts without an `/artists` page, i.e. those that aren't labels
+    fn scrape_roster_page(&self, url: &Url) -> Option<Vec<RosterItem>> {
+        let page = match self.client.get_page_if_found(url)? {
+            Some(page) => page,
+            None => return None,
+        };
+        let document = Document::parse(&page, &self.drift);
+        Some(Result::from_iter(document.select("li.artists-grid-item[data-band-id]")?.into_iter().map(|li| {
+            let band_id = document.parse_attr(li, "data-band-id")?;
+            let mut artist_url = page.url.join(document.attr(document.select_one_in(li, "a[href]")?, "href")?)?;
+            // Roster links carry `?label=...&tab=artists` to render the label's header on the artist page
+            artist_url.set_query(None);
+            Ok::<_, Error>(RosterItem { band_id, url: artist_url.join("/")?.to_string() })
+        }))?)
+    }
+
+    #[fehler::throws]
+    #[tracing::instrument(skip(self), fields(%url))]
+    fn scrape_fan_page(&self, url: &Url) -> (Page, Url, FanPage) {
+        let page = self.client.get_page(url)?;
+        let document = Document::parse(&page, &self.drift);
+        let fan_page: FanPage = document.json(document.attr(document.select_one("#pagedata")?, "data-blob")?)?;
+        let canonical = match document.canonical_url()? {
+            Some(canonical) => canonical,
+            None => Url::parse(&format!("https://bandcamp.com/{}", fan_page.fan_data.username))?,
+        };
+        self.alias(url, &canonical)?;
+        (page, canonical, fan_page)
+    }
+
+    #[fehler::throws]
+    #[tracing::instrument(skip(self), fields(url = %album.url))]
+    fn scrape_collectors_api(&self, album: &Album, token: &str) -> (Page, Thumbs) {
+        let url = Url::parse(&album.url)?.join("/api/tralbumcollectors/2/thumbs")?;
+        let AlbumId(kind, id) = album.id;
+        let page = self.client.post(&url, &serde_json::json!({
+            "tralbum_type": TralbumType::from(kind),
+            "tralbum_id": id,
             "token": token,
             "count": 80,
-        }))?.parse_json()?
+        }))?;
+        let response = page.parse_json(&page.body, &self.drift)?;
+        (page, response)
+    }
+
+    #[fehler::throws]
+    #[tracing::instrument(skip(self))]
+    fn scrape_discover_api(&self, tag: &str, cursor: &str) -> (Page, Discover) {
+        let url = Url::parse("https://bandcamp.com/api/discover/1/discover_web")?;
+        let page = self.client.post(&url, &serde_json::json!({
+            "category_id": 0,
+            "tag_norm_names": [tag],
+            "geoname_id": 0,
+            "slice": "top",
+            "cursor": cursor,
+            "size": 60,
+            "include_result_types": ["a", "s"],
+        }))?;
+        let response = page.parse_json(&page.body, &self.drift)?;
+        (page, response)
+    }
+
+    #[fehler::throws]
+    #[tracing::instrument(skip(self))]
+    fn scrape_following_api(&self, fan_id: u64, token: &str) -> (Page, Following) {
+        let url = Url::parse("https://bandcamp.com/api/fancollection/1/following_bands")?;
+        let page = self.client.post(&url, &serde_json::json!({
+            "fan_id": fan_id,
+            "older_than_token": token,
+            "count": 20,
+        }))?;
+        let response = page.parse_json(&page.body, &self.drift)?;
+        (page, response)
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self))]
-    fn scrape_collections_api(&self, fan_id: u64, token: &str) -> Collections {
+    fn scrape_collections_api(&self, fan_id: u64, token: &str) -> (Page, Collections) {
         let url = Url::parse("https://bandcamp.com/api/fancollection/1/collection_items")?;
-        self.client.post(&url, &serde_json::json!({
+        let page = self.client.post(&url, &serde_json::json!({
             "fan_id": fan_id,
             "older_than_token": token,
             "count": 20,
-        }))?.parse_json()?
+        }))?;
+        let response = page.parse_json(&page.body, &self.drift)?;
+        (page, response)
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,629 @@
+use std::time::{Duration, UNIX_EPOCH};
+use url::Url;
+use opt::data::{Album, AlbumId, AlbumInfo, Artist, ArtistId, Data, EntityData, ItemKind, Label, LabelId, Purchase, User, UserId};
+
+use crate::background::{error::{ErrorKind, ScrapeError}, event::{Budget, CancelToken, Cursor, Event, Events}, web::Client, SearchKind, SearchResult};
+use crate::background::drift::Drift;
+use super::{parse_band_page, Page, Scraper};
+
+fn page(url: &str, body: &str) -> Page {
+    Page { url: Url::parse(url).unwrap(), body: body.to_owned(), cache_id: None }
+}
+
+#[test]
+fn artist_music_grid_includes_client_items() {
+    let page = parse_band_page(&page("https://example-artist.bandcamp.com/music", include_str!("testdata/artist-music.html")), &Drift::default()).unwrap();
+
+    assert_eq!(page.band.id, 1111111111);
+    assert_eq!(page.releases.unwrap(), [
+        "https://example-artist.bandcamp.com/album/first",
+        "https://example-artist.bandcamp.com/album/second",
+        "https://example-artist.bandcamp.com/track/third",
+        "https://example-artist.bandcamp.com/album/fourth",
+        "https://example-artist.bandcamp.com/track/fifth",
+    ]);
+}
+
+#[test]
+fn label_music_grid_on_custom_domain() {
+    let page = parse_band_page(&page("https://records.example.com/music", include_str!("testdata/label-music.html")), &Drift::default()).unwrap();
+
+    assert_eq!(page.band.id, 4444444444);
+    assert_eq!(page.releases.unwrap(), [
+        "https://signed-artist.bandcamp.com/album/signed-debut",
+        "https://records.example.com/album/in-house",
+        "https://records.example.com/album/compilation",
+    ]);
+}
+
+#[test]
+fn featured_release_has_no_grid() {
+    let page = parse_band_page(&page("https://example-artist.bandcamp.com/", include_str!("testdata/artist-featured.html")), &Drift::default()).unwrap();
+
+    assert_eq!(page.band.id, 1111111111);
+    assert_eq!(page.releases, None);
+}
+
+fn scraper(pages: &[(&str, Option<serde_json::Value>, &str)]) -> Scraper {
+    scraper_with_drift(pages, Drift::default())
+}
+
+fn scraper_with_drift(pages: &[(&str, Option<serde_json::Value>, &str)], drift: Drift) -> Scraper {
+    let client = Client::offline().unwrap();
+    for (url, data, body) in pages {
+        client.insert(&Url::parse(url).unwrap(), data.as_ref(), body).unwrap();
+    }
+    Scraper::new(client, drift)
+}
+
+fn url(url: &str) -> Url {
+    Url::parse(url).unwrap()
+}
+
+fn fan(id: u64, username: &str) -> User {
+    User { id: UserId(id), url: format!("https://bandcamp.com/{username}") }
+}
+
+fn album(kind: ItemKind, id: u64, url: &str) -> Album {
+    Album { id: AlbumId(kind, id), url: url.to_owned() }
+}
+
+fn purchase(album: Album, added: u64, info: AlbumInfo) -> Purchase {
+    Purchase { album, added: UNIX_EPOCH + Duration::from_secs(added), info }
+}
+
+fn info(title: &str, artist: &str, genre_id: Option<u32>, featured_track: Option<(u64, &str)>) -> AlbumInfo {
+    AlbumInfo {
+        title: title.to_owned(),
+        artist: artist.to_owned(),
+        genre_id,
+        featured_track: featured_track.map(|(id, title)| (id, title.to_owned())),
+    }
+}
+
+fn signed_debut() -> Album {
+    album(ItemKind::Album, 6000000001, "https://signed-artist.bandcamp.com/album/signed-debut")
+}
+
+fn signed_debut_info() -> AlbumInfo {
+    info("Signed Debut", "Signed Artist", Some(2), Some((6000000011, "Opener")))
+}
+
+fn third() -> Album {
+    album(ItemKind::Track, 3000000003, "https://example-artist.bandcamp.com/track/third")
+}
+
+fn third_info() -> AlbumInfo {
+    info("Third", "Example Artist", Some(10), Some((3000000003, "Third")))
+}
+
+fn thumbs(tralbum_type: &str, tralbum_id: u64, token: &str) -> Option<serde_json::Value> {
+    Some(serde_json::json!({ "tralbum_type": tralbum_type, "tralbum_id": tralbum_id, "token": token, "count": 80 }))
+}
+
+fn collection(fan_id: u64, token: &str) -> Option<serde_json::Value> {
+    Some(serde_json::json!({ "fan_id": fan_id, "older_than_token": token, "count": 20 }))
+}
+
+fn following(fan_id: u64, token: &str) -> Option<serde_json::Value> {
+    Some(serde_json::json!({ "fan_id": fan_id, "older_than_token": token, "count": 20 }))
+}
+
+fn discover(cursor: &str) -> Option<serde_json::Value> {
+    Some(serde_json::json!({
+        "category_id": 0,
+        "tag_norm_names": ["post-rock"],
+        "geoname_id": 0,
+        "slice": "top",
+        "cursor": cursor,
+        "size": 60,
+        "include_result_types": ["a", "s"],
+    }))
+}
+
+const THUMBS_API: &str = "https://signed-artist.bandcamp.com/api/tralbumcollectors/2/thumbs";
+const COLLECTION_API: &str = "https://bandcamp.com/api/fancollection/1/collection_items";
+const FOLLOWING_API: &str = "https://bandcamp.com/api/fancollection/1/following_bands";
+const DISCOVER_API: &str = "https://bandcamp.com/api/discover/1/discover_web";
+
+/// Runs a crawl until it finishes or fails, returning the events seen before any failure
+fn collect(events: Events<'_>) -> (Vec<Event>, eyre::Result<()>) {
+    let mut collected = Vec::new();
+    for event in events {
+        match event {
+            Ok(event) => collected.push(event),
+            Err(error) => return (collected, Err(error)),
+        }
+    }
+    (collected, Ok(()))
+}
+
+fn scrape_album(scraper: &Scraper, album_url: &str) -> (Vec<Event>, eyre::Result<()>) {
+    collect(scraper.scrape_album(&url(album_url)))
+}
+
+fn scrape_fan(scraper: &Scraper, fan_url: &str) -> Vec<Event> {
+    let (events, result) = collect(scraper.scrape_fan(&url(fan_url)));
+    result.unwrap();
+    events
+}
+
+#[test]
+fn album_with_more_thumbs_follows_tokens() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), include_str!("testdata/thumbs-1.json")),
+        (THUMBS_API, thumbs("a", 6000000001, "1680000000:105"), include_str!("testdata/thumbs-2.json")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+    result.unwrap();
+
+    let debut = album(ItemKind::Album, 6000000001, "https://signed-artist.bandcamp.com/album/signed-debut");
+    assert_eq!(scraped, [
+        Event::Header(debut.clone().into()),
+        Event::Credit(
+            debut.clone(),
+            Artist { id: ArtistId(7777777777), url: "https://signed-artist.bandcamp.com/".to_owned() },
+            Some(Label { id: LabelId(4444444444), url: "https://records.example.com/".to_owned() }),
+        ),
+        Event::Recommended(debut.clone(), vec![
+            album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first"),
+            album(ItemKind::Album, 6000000003, "https://records.example.com/album/in-house"),
+        ]),
+        Event::Fans(debut.clone(), vec![fan(101, "reviewer-one")]),
+        Event::Fans(debut.clone(), vec![fan(102, "fan-two"), fan(103, "fan-three")]),
+        Event::Fans(debut.clone(), vec![fan(104, "fan-four"), fan(105, "fan-five")]),
+        Event::Fans(debut, vec![fan(106, "fan-six")]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn track_without_more_thumbs_makes_no_api_requests() {
+    // No api responses are seeded, so any pagination request fails the offline client
+    let scraper = scraper(&[
+        ("https://example-artist.bandcamp.com/track/third", None, include_str!("testdata/track-no-more-thumbs.html")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://example-artist.bandcamp.com/track/third");
+    result.unwrap();
+
+    let third = album(ItemKind::Track, 3000000003, "https://example-artist.bandcamp.com/track/third");
+    assert_eq!(scraped, [
+        Event::Header(third.clone().into()),
+        Event::Credit(third.clone(), Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() }, None),
+        Event::Recommended(third.clone(), vec![]),
+        Event::Fans(third.clone(), vec![]),
+        Event::Fans(third, vec![fan(102, "fan-two")]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn album_without_collectors() {
+    let scraper = scraper(&[
+        ("https://example-artist.bandcamp.com/album/first", None, include_str!("testdata/album-no-collectors.html")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://example-artist.bandcamp.com/album/first");
+    result.unwrap();
+
+    let first = album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first");
+    assert_eq!(scraped, [
+        Event::Header(first.clone().into()),
+        Event::Credit(first.clone(), Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() }, None),
+        Event::Recommended(first.clone(), vec![]),
+        Event::Fans(first.clone(), vec![]),
+        Event::Fans(first, vec![]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn album_credits_fall_back_to_the_artist() {
+    let first = include_str!("testdata/album-no-collectors.html");
+    let (ld_json_start, ld_json_end) = (first.find(", \"publisher\"").unwrap(), first.find("}]}}").unwrap() + 3);
+    let self_released = format!("{}{}", &first[..ld_json_start], &first[ld_json_end..]);
+    let scraper = scraper(&[("https://example-artist.bandcamp.com/album/first", None, &self_released)]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://example-artist.bandcamp.com/album/first");
+    result.unwrap();
+
+    let first = album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first");
+    assert_eq!(scraped[1], Event::Credit(first, Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() }, None));
+}
+
+#[test]
+fn album_without_linked_credits_is_still_scraped() {
+    let unlinked = include_str!("testdata/album-no-collectors.html").replace("band_id", "unrelated_id");
+    let scraper = scraper(&[("https://example-artist.bandcamp.com/album/first", None, &unlinked)]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://example-artist.bandcamp.com/album/first");
+    result.unwrap();
+
+    let first = album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first");
+    assert_eq!(scraped, [
+        Event::Header(first.clone().into()),
+        Event::Recommended(first.clone(), vec![]),
+        Event::Fans(first.clone(), vec![]),
+        Event::Fans(first, vec![]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn album_aliases_resolve_to_its_canonical_url() {
+    let scraper = scraper(&[
+        ("https://music.example-artist.com/album/first", None, include_str!("testdata/album-no-collectors.html")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://music.example-artist.com/album/first");
+    result.unwrap();
+
+    let canonical = "https://example-artist.bandcamp.com/album/first";
+    assert!(matches!(&scraped[0], Event::Header(album) if album.url() == canonical));
+    assert_eq!(scraper.resolve(&url("https://music.example-artist.com/album/first?from=fanpub_fnb")).unwrap(), url(canonical));
+    assert_eq!(scraper.resolve(&url("https://example-artist.bandcamp.com/album/first#lyrics")).unwrap(), url(canonical));
+}
+
+#[test]
+fn pages_are_only_fetched_once_needed() {
+    // Only the album page is seeded, so fetching the next page of thumbs would fail
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+    ]);
+
+    let mut events = scraper.scrape_album(&url("https://signed-artist.bandcamp.com/album/signed-debut"));
+    assert_eq!(events.by_ref().take(5).filter(Result::is_ok).count(), 5);
+    assert!(events.next().unwrap().is_err());
+    assert!(events.next().is_none());
+}
+
+#[test]
+fn budget_leaves_album_partial_and_resumes_from_token() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), include_str!("testdata/thumbs-1.json")),
+        (THUMBS_API, thumbs("a", 6000000001, "1680000000:105"), include_str!("testdata/thumbs-2.json")),
+    ]);
+    let budget = Budget { max_fans: 3, max_collection_items: 100, max_pages: 100, max_time: Duration::from_secs(60) };
+
+    let (scraped, result) = collect(scraper.scrape_album(&url("https://signed-artist.bandcamp.com/album/signed-debut")).with_budget(budget.clone()));
+    result.unwrap();
+
+    let debut = album(ItemKind::Album, 6000000001, "https://signed-artist.bandcamp.com/album/signed-debut");
+    let cursor = Cursor::Fans { album: debut.clone(), token: "1700000001:103".to_owned() };
+    assert_eq!(scraped.last(), Some(&Event::Partial(cursor.clone())));
+
+    let (scraped, result) = collect(scraper.resume(cursor).with_budget(budget));
+    result.unwrap();
+
+    assert_eq!(scraped, [
+        Event::Header(debut.clone().into()),
+        Event::Fans(debut.clone(), vec![fan(104, "fan-four"), fan(105, "fan-five")]),
+        Event::Fans(debut, vec![fan(106, "fan-six")]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn cancelling_stops_before_the_next_page() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+    ]);
+    let cancel = CancelToken::default();
+
+    let mut scraped = Vec::new();
+    for event in scraper.scrape_album(&url("https://signed-artist.bandcamp.com/album/signed-debut")).with_cancel(cancel.clone()) {
+        // The thumbs api pages aren't cached, so fetching any would fail
+        let event = event.unwrap();
+        if matches!(event, Event::Header(_)) {
+            cancel.cancel();
+        }
+        scraped.push(event);
+    }
+
+    let debut = album(ItemKind::Album, 6000000001, "https://signed-artist.bandcamp.com/album/signed-debut");
+    assert_eq!(scraped.len(), 6);
+    assert_eq!(scraped.last(), Some(&Event::Partial(Cursor::Fans { album: debut, token: "1700000001:103".to_owned() })));
+
+    let (scraped, result) = collect(scraper.scrape_album(&url("https://signed-artist.bandcamp.com/album/signed-debut")).with_cancel(cancel));
+    result.unwrap();
+    assert_eq!(scraped, [Event::Cancelled]);
+}
+
+#[test]
+fn empty_last_thumbs_page_ends_the_fans() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), include_str!("testdata/thumbs-last-empty.json")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+    result.unwrap();
+
+    let debut = album(ItemKind::Album, 6000000001, "https://signed-artist.bandcamp.com/album/signed-debut");
+    assert_eq!(scraped[5..], [Event::Fans(debut, vec![]), Event::Done]);
+}
+
+#[test]
+fn empty_thumbs_page_with_more_available_is_a_pagination_error() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), include_str!("testdata/thumbs-empty.json")),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+
+    assert_eq!(scraped.len(), 5);
+    let error = result.unwrap_err();
+    let error = error.downcast_ref::<ScrapeError>().unwrap();
+    assert!(matches!(error.kind, ErrorKind::Pagination { .. }), "{error}");
+    assert_eq!(error.url.as_str(), THUMBS_API);
+    assert!(error.cache_id.is_some());
+}
+
+#[test]
+fn invalid_ids_are_reported_with_the_offending_element() {
+    let album_page = include_str!("testdata/album-more-thumbs.html").replace(r#"data-albumid="6000000003""#, r#"data-albumid="in-house""#);
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, &album_page),
+    ]);
+
+    let (scraped, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+
+    assert_eq!(scraped, []);
+    let error = result.unwrap_err();
+    let error = error.downcast_ref::<ScrapeError>().unwrap();
+    assert!(matches!(&error.kind, ErrorKind::InvalidAttribute { attribute, value, .. } if attribute == "data-albumid" && value == "in-house"), "{error}");
+    assert!(error.snippet.as_deref().is_some_and(|snippet| snippet.contains(r#"data-albumtitle="In House""#)), "{error}");
+}
+
+#[test]
+fn collection_items_missing_from_the_item_cache() {
+    let fan_page = include_str!("testdata/fan-small-collection.html").replace("&quot;sequence&quot;:[&quot;a6000000001&quot;", "&quot;sequence&quot;:[&quot;a6000000009&quot;");
+    let scraper = scraper(&[
+        ("https://bandcamp.com/fan-two", None, &fan_page),
+    ]);
+
+    let (_, result) = collect(scraper.scrape_fan(&url("https://bandcamp.com/fan-two")));
+
+    let error = result.unwrap_err();
+    let error = error.downcast_ref::<ScrapeError>().unwrap();
+    assert!(matches!(&error.kind, ErrorKind::MissingItem { key, .. } if key == "a6000000009"), "{error}");
+}
+
+#[test]
+fn fan_with_small_collection_makes_no_api_requests() {
+    let scraper = scraper(&[
+        ("https://bandcamp.com/fan-two", None, include_str!("testdata/fan-small-collection.html")),
+    ]);
+
+    assert_eq!(scrape_fan(&scraper, "https://bandcamp.com/fan-two"), [
+        Event::Header(fan(102, "fan-two").into()),
+        Event::Expected(2),
+        Event::Collection(fan(102, "fan-two"), vec![
+            purchase(signed_debut(), 1672567200, signed_debut_info()),
+            purchase(third(), 1678905000, third_info()),
+        ]),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn fan_with_large_collection_follows_tokens() {
+    let scraper = scraper(&[
+        ("https://bandcamp.com/fan-three", None, include_str!("testdata/fan-large-collection.html")),
+        (COLLECTION_API, collection(103, "1672567200:3000000003:t::"), include_str!("testdata/collection-1.json")),
+        (COLLECTION_API, collection(103, "1672567200:3000000002:a::"), include_str!("testdata/collection-2.json")),
+        (FOLLOWING_API, following(103, "1650000000:4444444444"), include_str!("testdata/following-1.json")),
+    ]);
+
+    let events = scrape_fan(&scraper, "https://bandcamp.com/fan-three");
+    assert_eq!(events, [
+        Event::Header(fan(103, "fan-three").into()),
+        Event::Expected(5),
+        Event::Collection(fan(103, "fan-three"), vec![
+            purchase(signed_debut(), 1672567200, signed_debut_info()),
+            purchase(third(), 1672567200, third_info()),
+        ]),
+        Event::Follows(fan(103, "fan-three"), vec![
+            Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() },
+            Artist { id: ArtistId(4444444444), url: "https://records.example.com/".to_owned() },
+        ]),
+        Event::Collection(fan(103, "fan-three"), vec![
+            purchase(album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first"), 1672567200, info("First", "Example Artist", Some(6), Some((3000000011, "First Light")))),
+            purchase(album(ItemKind::Album, 3000000002, "https://example-artist.bandcamp.com/album/second"), 1672567200, info("Second", "Example Artist", None, None)),
+        ]),
+        Event::Collection(fan(103, "fan-three"), vec![
+            purchase(album(ItemKind::Album, 6000000003, "https://records.example.com/album/in-house"), 1672567200, info("In House", "Example Records", Some(1), Some((6000000031, "House Track")))),
+        ]),
+        Event::Follows(fan(103, "fan-three"), vec![
+            Artist { id: ArtistId(7777777777), url: "https://signed-artist.bandcamp.com/".to_owned() },
+        ]),
+        Event::Done,
+    ]);
+
+    // Example Records is a label, once it's known as one following it doesn't add it again as an artist
+    let mut data = Data::default();
+    let label = Label { id: LabelId(4444444444), url: "https://records.example.com/".to_owned() };
+    data.add_node(&label.clone().into());
+    for event in &events {
+        if let Event::Follows(user, artists) = event {
+            for artist in artists {
+                data.add_follow(user, artist);
+            }
+        }
+    }
+    assert!(!data.artists.contains_key(&ArtistId(4444444444)));
+    assert_eq!(data.urls[&label.url], data.labels[&label.id]);
+}
+
+#[test]
+fn artist_featured_root_falls_back_to_music_grid() {
+    let scraper = scraper(&[
+        ("https://example-artist.bandcamp.com/", None, include_str!("testdata/artist-featured.html")),
+        ("https://example-artist.bandcamp.com/music", None, include_str!("testdata/artist-music.html")),
+    ]);
+
+    let (scraped, result) = collect(scraper.scrape_artist(&url("https://example-artist.bandcamp.com/")));
+    result.unwrap();
+
+    assert_eq!(scraped, [
+        Event::Header(Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() }.into()),
+        Event::Release("https://example-artist.bandcamp.com/album/first".to_owned()),
+        Event::Release("https://example-artist.bandcamp.com/album/second".to_owned()),
+        Event::Release("https://example-artist.bandcamp.com/track/third".to_owned()),
+        Event::Release("https://example-artist.bandcamp.com/album/fourth".to_owned()),
+        Event::Release("https://example-artist.bandcamp.com/track/fifth".to_owned()),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn signed_artist_links_its_label() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/", None, include_str!("testdata/artist-on-label.html")),
+        ("https://records.example.com/", None, include_str!("testdata/label-music.html")),
+    ]);
+
+    let (scraped, result) = collect(scraper.scrape_artist(&url("https://signed-artist.bandcamp.com/")));
+    result.unwrap();
+
+    let artist = Artist { id: ArtistId(7777777777), url: "https://signed-artist.bandcamp.com/".to_owned() };
+    assert_eq!(scraped, [
+        Event::Header(artist.clone().into()),
+        Event::Release("https://signed-artist.bandcamp.com/album/signed-debut".to_owned()),
+        Event::Signed(artist, Label { id: LabelId(4444444444), url: "https://records.example.com/".to_owned() }),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn label_roster_is_limited() {
+    let scraper = scraper(&[
+        ("https://records.example.com/music", None, include_str!("testdata/label-music.html")),
+        ("https://records.example.com/artists", None, include_str!("testdata/label-artists.html")),
+    ]);
+
+    let (scraped, result) = collect(scraper.scrape_label(&url("https://records.example.com/music"), 1));
+    result.unwrap();
+
+    let label = Label { id: LabelId(4444444444), url: "https://records.example.com/".to_owned() };
+    assert_eq!(scraped, [
+        Event::Header(label.clone().into()),
+        Event::Roster(label, vec![Artist { id: ArtistId(7777777777), url: "https://signed-artist.bandcamp.com/".to_owned() }]),
+        Event::Release("https://signed-artist.bandcamp.com/album/signed-debut".to_owned()),
+        Event::Release("https://records.example.com/album/in-house".to_owned()),
+        Event::Release("https://records.example.com/album/compilation".to_owned()),
+        Event::Done,
+    ]);
+}
+
+#[test]
+fn tag_follows_cursor_until_limit() {
+    let scraper = scraper(&[
+        (DISCOVER_API, discover("*"), include_str!("testdata/discover-1.json")),
+        (DISCOVER_API, discover("c2"), include_str!("testdata/discover-2.json")),
+    ]);
+
+    let releases = |tag, limit| -> Vec<String> {
+        scraper.scrape_tag(tag, limit).filter_map(|event| match event.unwrap() {
+            Event::Release(url) => Some(url),
+            _ => None,
+        }).collect()
+    };
+
+    assert_eq!(releases("Post Rock", 10), [
+        "https://signed-artist.bandcamp.com/album/signed-debut",
+        "https://example-artist.bandcamp.com/album/first",
+        "https://records.example.com/album/in-house",
+    ]);
+    assert_eq!(releases("post rock", 1), ["https://signed-artist.bandcamp.com/album/signed-debut"]);
+}
+
+#[test]
+fn search_results_of_every_kind() {
+    let scraper = scraper(&[
+        ("https://bandcamp.com/search?q=example+query&item_type=", None, include_str!("testdata/search.html")),
+    ]);
+
+    let results = scraper.scrape_search("example query", SearchKind::All).unwrap();
+
+    let result = |name: &str, entity: EntityData| SearchResult { name: name.to_owned(), entity };
+    assert_eq!(results, [
+        result("Example Artist", EntityData::Artist(Artist { id: ArtistId(1111111111), url: "https://example-artist.bandcamp.com/".to_owned() })),
+        result("Example Records", EntityData::Label(Label { id: LabelId(4444444444), url: "https://records.example.com/".to_owned() })),
+        result("First", EntityData::Album(album(ItemKind::Album, 3000000001, "https://example-artist.bandcamp.com/album/first"))),
+        result("Third", EntityData::Album(album(ItemKind::Track, 3000000003, "https://example-artist.bandcamp.com/track/third"))),
+        result("Fan Two", EntityData::User(fan(102, "fan-two"))),
+    ]);
+}
+
+#[test]
+fn drift_records_unknown_fields_per_endpoint() {
+    let fan_page = include_str!("testdata/fan-small-collection.html").replace("&quot;purchased&quot;", "&quot;gift_sender&quot;:null,&quot;purchased&quot;");
+    let scraper = scraper(&[
+        ("https://bandcamp.com/fan-two", None, &fan_page),
+    ]);
+
+    scrape_fan(&scraper, "https://bandcamp.com/fan-two");
+
+    let (unknown, missing) = scraper.drift().fields("FanPage");
+    assert_eq!(unknown, ["item_cache.collection.{}.gift_sender"]);
+    assert_eq!(missing, Vec::<String>::new());
+}
+
+#[test]
+fn fields_we_deliberately_ignore_are_not_drift() {
+    let scraper = scraper(&[
+        ("https://bandcamp.com/fan-two", None, include_str!("testdata/fan-small-collection.html")),
+        ("https://example-artist.bandcamp.com/album/first", None, include_str!("testdata/album-no-collectors.html")),
+    ]);
+
+    scrape_fan(&scraper, "https://bandcamp.com/fan-two");
+    scrape_album(&scraper, "https://example-artist.bandcamp.com/album/first").1.unwrap();
+
+    for endpoint in ["FanPage", "Properties", "Collectors", "LdJson"] {
+        assert_eq!(scraper.drift().fields(endpoint), (Vec::new(), Vec::new()), "{endpoint}");
+    }
+}
+
+#[test]
+fn drift_records_missing_fields() {
+    let scraper = scraper(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), r#"{ "results": [{ "fan_id": 104, "username": "fan-four" }], "more_available": false }"#),
+    ]);
+
+    let (_, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+
+    assert!(result.is_err());
+    assert_eq!(scraper.drift().fields("Thumbs").1, ["results[].token"]);
+}
+
+#[test]
+fn strict_drift_fails_the_page() {
+    let scraper = scraper_with_drift(&[
+        ("https://signed-artist.bandcamp.com/album/signed-debut", None, include_str!("testdata/album-more-thumbs.html")),
+        (THUMBS_API, thumbs("a", 6000000001, "1700000001:103"), r#"{ "results": [{ "fan_id": 104, "username": "fan-four", "token": "1690000000:104", "is_montage_image": false }], "more_available": false }"#),
+    ], Drift::new(true));
+
+    let (_, result) = scrape_album(&scraper, "https://signed-artist.bandcamp.com/album/signed-debut");
+
+    let error = result.unwrap_err();
+    let error = error.downcast_ref::<ScrapeError>().unwrap();
+    assert!(matches!(&error.kind, ErrorKind::SchemaDrift { endpoint, unknown } if endpoint == "Thumbs" && unknown == &["results[].is_montage_image"]), "{error}");
+}
+
+#[test]
+fn clean_pages_pass_strict_drift() {
+    let scraper = scraper_with_drift(&[
+        ("https://example-artist.bandcamp.com/album/first", None, include_str!("testdata/album-no-collectors.html")),
+        ("https://bandcamp.com/fan-two", None, include_str!("testdata/fan-small-collection.html")),
+    ], Drift::new(true));
+
+    scrape_album(&scraper, "https://example-artist.bandcamp.com/album/first").1.unwrap();
+    scrape_fan(&scraper, "https://bandcamp.com/fan-two");
+}
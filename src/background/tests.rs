@@ -0,0 +1,173 @@
+use super::*;
+
+fn config() -> Config {
+    Config {
+        roster_limit: 10,
+        tag_limit: 10,
+        strict_schema: false,
+        budget: Budget { max_fans: 100, max_collection_items: 100, max_pages: 100, max_time: Duration::from_secs(60) },
+        crawl: None,
+        refresh_after: None,
+    }
+}
+
+fn client(pages: &[(&str, &str)]) -> self::web::Client {
+    let client = self::web::Client::offline().unwrap();
+    for (url, body) in pages {
+        client.insert(&Url::parse(url).unwrap(), None, body).unwrap();
+    }
+    client
+}
+
+/// Handles the requests until the background goes idle, returning every response and the background to inspect
+fn run(client: self::web::Client, requests: Vec<(Request, Priority)>) -> (Vec<Response>, Background) {
+    run_with(client, requests, |_| {})
+}
+
+fn run_with(client: self::web::Client, requests: Vec<(Request, Priority)>, setup: impl FnOnce(&mut Background)) -> (Vec<Response>, Background) {
+    let (to_scrape_tx, to_scrape_rx) = crossbeam::channel::unbounded();
+    let (scraped_tx, scraped_rx) = crossbeam::channel::unbounded();
+    for request in requests {
+        to_scrape_tx.send(request).unwrap();
+    }
+    let mut background = Background::new(config(), client, to_scrape_rx, scraped_tx).unwrap();
+    setup(&mut background);
+    let thread = std::thread::spawn(move || {
+        background.run();
+        background
+    });
+    let mut responses = Vec::new();
+    for response in &scraped_rx {
+        let idle = matches!(response, Response::Idle);
+        responses.push(response);
+        if idle {
+            break;
+        }
+    }
+    drop(to_scrape_tx);
+    (responses, thread.join().unwrap())
+}
+
+/// The responses the ui tracks a request's lifecycle by
+fn lifecycle(responses: &[Response]) -> Vec<String> {
+    responses.iter().filter_map(|response| match response {
+        Response::Started { url, .. } => Some(format!("started {url:?}")),
+        Response::Progress { fetched, expected } => Some(format!("progress {fetched} of {expected:?}")),
+        Response::Failed { url, .. } => Some(format!("failed {url:?}")),
+        Response::Scraped(entity, _) => Some(format!("scraped {}", entity.url())),
+        Response::Idle => Some("idle".to_owned()),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn requests_report_their_lifecycle_in_order() {
+    let client = client(&[
+        ("https://example-artist.bandcamp.com/album/first", include_str!("scrape/testdata/album-no-collectors.html")),
+    ]);
+
+    let (responses, _) = run(client, vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first?from=discover".to_owned() }, Priority::User),
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/missing".to_owned() }, Priority::User),
+    ]);
+
+    assert_eq!(lifecycle(&responses), [
+        "started Some(\"https://example-artist.bandcamp.com/album/first\")",
+        "scraped https://example-artist.bandcamp.com/album/first",
+        "started Some(\"https://example-artist.bandcamp.com/album/missing\")",
+        "failed Some(\"https://example-artist.bandcamp.com/album/missing\")",
+        "idle",
+    ]);
+}
+
+#[test]
+fn queued_requests_are_reported_by_canonical_url() {
+    let (responses, _) = run(client(&[]), vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first?from=discover#lyrics".to_owned() }, Priority::User),
+        (Request::Tag { tag: "ambient".to_owned() }, Priority::User),
+    ]);
+
+    let queued = responses.iter().filter_map(|response| match response {
+        Response::Queued { url } => Some(url.as_str()),
+        _ => None,
+    }).collect::<Vec<_>>();
+    assert_eq!(queued, ["https://example-artist.bandcamp.com/album/first"]);
+}
+
+#[test]
+fn a_panicking_request_fails_alone() {
+    let client = client(&[
+        ("https://example-artist.bandcamp.com/album/first", include_str!("scrape/testdata/album-no-collectors.html")),
+    ]);
+
+    let (responses, background) = run_with(client, vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/second".to_owned() }, Priority::User),
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first".to_owned() }, Priority::User),
+    ], |background| background.panic_on = Some("https://example-artist.bandcamp.com/album/second".to_owned()));
+
+    assert_eq!(lifecycle(&responses), [
+        "started Some(\"https://example-artist.bandcamp.com/album/second\")",
+        "failed Some(\"https://example-artist.bandcamp.com/album/second\")",
+        "started Some(\"https://example-artist.bandcamp.com/album/first\")",
+        "scraped https://example-artist.bandcamp.com/album/first",
+        "idle",
+    ]);
+    assert!(responses.iter().any(|response| matches!(response, Response::Failed { error, .. } if error.contains("panicking on request"))));
+    assert!(background.scraper.client().queued().unwrap().is_empty());
+}
+
+#[test]
+fn a_restarted_background_keeps_what_was_scraped() {
+    let (_, previous) = run(client(&[
+        ("https://example-artist.bandcamp.com/album/first", include_str!("scrape/testdata/album-no-collectors.html")),
+    ]), vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first".to_owned() }, Priority::User),
+    ]);
+
+    let (_to_scrape_tx, to_scrape_rx) = crossbeam::channel::unbounded();
+    let (scraped_tx, _scraped_rx) = crossbeam::channel::unbounded();
+    let restarted = Background::new(config(), client(&[]), to_scrape_rx, scraped_tx).unwrap().carry_over(previous);
+    assert_eq!(restarted.claim("https://example-artist.bandcamp.com/album/first").unwrap(), None);
+}
+
+#[test]
+fn scraped_entities_are_timed_by_their_cached_page() {
+    let client = client(&[
+        ("https://example-artist.bandcamp.com/album/first", include_str!("scrape/testdata/album-no-collectors.html")),
+    ]);
+    let retrieved = client.retrieved(&Url::parse("https://example-artist.bandcamp.com/album/first").unwrap()).unwrap().unwrap();
+
+    let (responses, _) = run(client, vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first".to_owned() }, Priority::User),
+    ]);
+
+    assert!(responses.iter().any(|response| matches!(response, Response::Scraped(_, at) if *at == retrieved)));
+}
+
+#[test]
+fn stale_entities_are_queued_again_behind_everything_else() {
+    let (_, background) = run_with(client(&[
+        ("https://example-artist.bandcamp.com/album/first", include_str!("scrape/testdata/album-no-collectors.html")),
+    ]), vec![
+        (Request::Album { url: "https://example-artist.bandcamp.com/album/first?from=discover".to_owned() }, Priority::User),
+    ], |background| background.config.refresh_after = Some(Duration::ZERO));
+
+    background.requeue_stale();
+
+    let url = "https://example-artist.bandcamp.com/album/first";
+    assert_eq!(background.queue.borrow().priority(url), Some(Priority::Refresh));
+    assert_eq!(background.queue.borrow().len(), 1);
+    assert!(background.done.borrow().is_empty());
+}
+
+#[test]
+fn failed_continuations_report_their_cursor() {
+    let album = Album { id: opt::data::AlbumId(opt::data::ItemKind::Album, 6000000001), url: "https://signed-artist.bandcamp.com/album/signed-debut".to_owned() };
+    let cursor = Cursor::Fans { album, token: "1700000001:103".to_owned() };
+
+    // Nothing is cached, so the next page of fans fails
+    let (responses, _) = run(client(&[]), vec![(Request::Continue { cursor: cursor.clone() }, Priority::User)]);
+
+    assert!(responses.iter().any(|response| matches!(response, Response::Failed { url: Some(url), cursor: Some(failed), .. }
+        if url == "https://signed-artist.bandcamp.com/album/signed-debut" && *failed == cursor)), "{responses:?}");
+}
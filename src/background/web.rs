@@ -2,13 +2,70 @@ use eyre::Error;
 use chrono::{offset::Utc, DateTime};
 use rusqlite::{named_params, OptionalExtension, types::{ToSqlOutput, ValueRef}, ToSql};
 use url::Url;
-use std::{time::{Instant, Duration}, cell::Cell};
+use std::{time::{Instant, Duration, SystemTime}, cell::Cell};
+use super::{crawl::{Crawl, CrawlConfig}, drift::Drift, error::{ErrorKind, ScrapeError}, Cursor, Priority, Request};
 
 #[derive(Debug)]
 pub(crate) struct Client {
     client: reqwest::blocking::Client,
     cache: rusqlite::Connection,
     last_request: Cell<Instant>,
+    /// Only serve from the cache, used to run the scraper against saved pages
+    offline: bool,
+    /// Pages retrieved longer ago than this are fetched again
+    max_age: Option<Duration>,
+}
+
+/// A response body along with the url it was finally retrieved from after following redirects
+#[derive(Debug)]
+pub(crate) struct Page {
+    pub(crate) url: Url,
+    pub(crate) body: String,
+    pub(crate) cache_id: Option<i64>,
+}
+
+impl Page {
+    /// Attaches this page's url, cache row and a snippet of its body to an error
+    pub(crate) fn error(&self, kind: ErrorKind) -> ScrapeError {
+        self.error_at(kind, None)
+    }
+
+    /// Like [`Page::error`] with the snippet taken around `offset`, or the start of the body if the point of failure
+    /// isn't known
+    pub(crate) fn error_at(&self, kind: ErrorKind, offset: Option<usize>) -> ScrapeError {
+        let offset = offset.or_else(|| self.body.find("<body")).unwrap_or(0);
+        ScrapeError {
+            kind,
+            url: self.url.clone(),
+            cache_id: self.cache_id,
+            snippet: Some(super::error::snippet(&self.body, offset)),
+        }
+    }
+
+    /// Parses json extracted from this page (or the whole body for api responses), reporting the path of any failure
+    /// and recording fields that don't match `T`
+    #[fehler::throws]
+    pub(crate) fn parse_json<T: serde::de::DeserializeOwned>(&self, text: &str, drift: &Drift) -> T {
+        let endpoint = endpoint::<T>();
+        let mut unknown = Vec::new();
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let mut on_unknown = |path: serde_ignored::Path<'_>| unknown.extend(drift.unknown(&endpoint, &path));
+        let deserializer = serde_ignored::Deserializer::new(&mut deserializer, &mut on_unknown);
+        let value = serde_path_to_error::deserialize(deserializer).map_err(|error| {
+            if let Some(path) = super::drift::missing_field::<T>(text) {
+                drift.missing(&endpoint, path);
+            }
+            let offset = super::error::offset_of(text, error.inner().line(), error.inner().column());
+            ScrapeError {
+                snippet: Some(super::error::snippet(text, offset)),
+                ..self.error(ErrorKind::Json(error))
+            }
+        })?;
+        if drift.is_strict() && !unknown.is_empty() {
+            fehler::throw!(self.error(ErrorKind::SchemaDrift { endpoint, unknown }));
+        }
+        value
+    }
 }
 
 #[derive(Debug, strum::AsRefStr)]
@@ -50,8 +107,17 @@ impl DebugExt for serde_json::Value {
 impl Client {
     #[fehler::throws]
     pub(crate) fn new() -> Self {
-        let mut cache = rusqlite::Connection::open("web-cache.sqlite")?;
+        Self::with_cache(rusqlite::Connection::open("web-cache.sqlite")?, false)?
+    }
+
+    #[cfg(test)]
+    #[fehler::throws]
+    pub(crate) fn offline() -> Self {
+        Self::with_cache(rusqlite::Connection::open_in_memory()?, true)?
+    }
 
+    #[fehler::throws]
+    fn with_cache(mut cache: rusqlite::Connection, offline: bool) -> Self {
         let migrations = [
             "create table pages (id integer primary key) strict",
             "alter table pages add column url text not null",
@@ -60,6 +126,12 @@ impl Client {
             "alter table pages add column response text not null",
             "alter table pages add column retrieved text not null",
             "create unique index pages_index on pages (url, method, data)",
+            "alter table pages add column final_url text",
+            "create table aliases (alias text primary key, canonical text not null) strict",
+            "create table queue (key text primary key, priority text not null, queued integer not null, request text not null) strict",
+            "create table partials (url text primary key, cursor text not null) strict",
+            "create table crawl (id integer primary key check (id = 0), progress text not null) strict",
+            "create table missing (url text primary key, retrieved text not null) strict",
         ];
 
         let version: u32 = cache.pragma_query_value(None, "user_version", |row| row.get("user_version"))?;
@@ -76,57 +148,216 @@ impl Client {
             client: reqwest::blocking::Client::new(),
             cache,
             last_request: Cell::new(Instant::now()),
+            offline,
+            max_age: None,
+        }
+    }
+
+    pub(crate) fn with_max_age(self, max_age: Option<Duration>) -> Self {
+        Self { max_age, ..self }
+    }
+
+    /// Seeds the cache with a saved response, as if it had been fetched from `url`
+    #[cfg(test)]
+    #[fehler::throws]
+    pub(crate) fn insert(&self, url: &Url, data: Option<&serde_json::Value>, body: &str) {
+        let method = if data.is_some() { Method::Post } else { Method::Get };
+        self.add_to_cache(url, method, data, &Page { url: url.clone(), body: body.to_owned(), cache_id: None })?;
+    }
+
+    /// Remembers that `alias` refers to the same page as `canonical`
+    #[fehler::throws]
+    #[tracing::instrument(skip(self), fields(%alias, %canonical))]
+    pub(crate) fn add_alias(&self, alias: &Url, canonical: &Url) {
+        self.cache.execute(
+            "insert or replace into aliases (alias, canonical) values (:alias, :canonical)",
+            named_params!(":alias": alias, ":canonical": canonical),
+        )?;
+    }
+
+    /// Looks up the canonical url previously recorded for `url`, if any
+    #[fehler::throws]
+    pub(crate) fn canonical(&self, url: &Url) -> Option<Url> {
+        self.cache.query_row(
+            "select canonical from aliases where alias = :alias",
+            named_params!(":alias": url),
+            |row| row.get::<_, Url>("canonical"),
+        ).optional()?
+    }
+
+    /// Persists a queued request so it survives restarts, if it was already saved only its priority is raised and it
+    /// moves behind the requests saved since
+    #[fehler::throws]
+    pub(crate) fn save_queued(&self, key: &str, priority: Priority, request: &Request) {
+        self.cache.execute(
+            "insert into queue (key, priority, queued, request)
+            values (:key, :priority, (select coalesce(max(queued), 0) + 1 from queue), :request)
+            on conflict (key) do update set priority = excluded.priority, queued = excluded.queued",
+            named_params!(":key": key, ":priority": priority.as_ref(), ":request": serde_json::to_value(request)?),
+        )?;
+    }
+
+    #[fehler::throws]
+    pub(crate) fn remove_queued(&self, key: &str) {
+        self.cache.execute("delete from queue where key = :key", named_params!(":key": key))?;
+    }
+
+    #[fehler::throws]
+    pub(crate) fn clear_queued(&self) {
+        self.cache.execute("delete from queue", ())?;
+    }
+
+    /// The requests left over from a previous run, in the order they were queued
+    #[fehler::throws]
+    pub(crate) fn queued(&self) -> Vec<(String, Priority, Request)> {
+        let mut statement = self.cache.prepare("select key, priority, request from queue order by queued")?;
+        let rows = statement.query_map((), |row| {
+            Ok((row.get::<_, String>("key")?, row.get::<_, String>("priority")?, row.get::<_, serde_json::Value>("request")?))
+        })?;
+        let mut queued = Vec::new();
+        for row in rows {
+            let (key, priority, request) = row?;
+            let priority = priority.parse().map_err(|_| eyre::eyre!("unknown priority {priority:?} for {key}"))?;
+            queued.push((key, priority, serde_json::from_value(request)?));
+        }
+        queued
+    }
+
+    /// Remembers where a partially scraped entity left off, replacing any earlier cursor for it
+    #[fehler::throws]
+    pub(crate) fn save_partial(&self, cursor: &Cursor) {
+        self.cache.execute(
+            "insert or replace into partials (url, cursor) values (:url, :cursor)",
+            named_params!(":url": cursor.entity().url(), ":cursor": serde_json::to_value(cursor)?),
+        )?;
+    }
+
+    #[fehler::throws]
+    pub(crate) fn remove_partial(&self, url: &str) {
+        self.cache.execute("delete from partials where url = :url", named_params!(":url": url))?;
+    }
+
+    #[fehler::throws]
+    pub(crate) fn partials(&self) -> Vec<Cursor> {
+        let mut statement = self.cache.prepare("select cursor from partials")?;
+        let rows = statement.query_map((), |row| row.get::<_, serde_json::Value>("cursor"))?;
+        let mut partials = Vec::new();
+        for row in rows {
+            partials.push(serde_json::from_value(row?)?);
+        }
+        partials
+    }
+
+    /// When the page at `url` was last fetched from the server, whether it was requested there or redirected to it
+    #[fehler::throws]
+    pub(crate) fn retrieved(&self, url: &Url) -> Option<SystemTime> {
+        self.cache.query_row(
+            "select max(retrieved) as retrieved from pages where method = :method and (url = :url or final_url = :url)",
+            named_params!(":url": url, ":method": Method::Get),
+            |row| row.get::<_, Option<DateTime<Utc>>>("retrieved"),
+        )?.map(SystemTime::from)
+    }
+
+    /// Remembers where the automatic crawl has got to, replacing what was saved before
+    #[fehler::throws]
+    pub(crate) fn save_crawl(&self, crawl: &Crawl) {
+        self.cache.execute(
+            "insert or replace into crawl (id, progress) values (0, :progress)",
+            named_params!(":progress": serde_json::to_value(crawl)?),
+        )?;
+    }
+
+    /// The crawl saved by a previous run, if any, carried on with the given limits
+    #[fehler::throws]
+    pub(crate) fn crawl(&self, config: CrawlConfig) -> Option<Crawl> {
+        let progress = self.cache.query_row("select progress from crawl", (), |row| row.get::<_, serde_json::Value>("progress")).optional()?;
+        match progress {
+            Some(progress) => Some(Crawl::restore(config, progress)?),
+            None => None,
         }
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url))]
-    pub(crate) fn get(&self, url: &Url) -> String {
-        if let Some(response) = self.get_from_cache(url, Method::Get, None)? {
-            response
+    pub(crate) fn get_page(&self, url: &Url) -> Page {
+        if let Some(page) = self.get_from_cache(url, Method::Get, None)? {
+            page
         } else {
-            let response = self.get_from_server(url)?;
-            self.add_to_cache(url, Method::Get, None, &response)?;
-            response
+            let mut page = self.get_from_server(url)?;
+            page.cache_id = Some(self.add_to_cache(url, Method::Get, None, &page)?);
+            page
         }
     }
 
+    /// Like [`Client::get_page`], but `None` if the server has no page at `url`
+    ///
+    /// Missing pages are remembered like cached ones, so they aren't asked for again until they expire.
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url))]
-    pub(crate) fn post(&self, url: &Url, data: &serde_json::Value) -> String {
-        if let Some(response) = self.get_from_cache(url, Method::Post, Some(data))? {
-            response
+    pub(crate) fn get_page_if_found(&self, url: &Url) -> Option<Page> {
+        let missing = self.cache.query_row(
+            "select retrieved from missing where url = :url",
+            named_params!(":url": url),
+            |row| row.get::<_, DateTime<Utc>>("retrieved"),
+        ).optional()?;
+        match missing {
+            Some(retrieved) if !self.expired(retrieved) => {
+                tracing::info!(%retrieved, "cached as missing");
+                return None;
+            }
+            _ => {}
+        }
+        match self.get_page(url) {
+            Ok(page) => Some(page),
+            Err(error) if matches!(error.downcast_ref::<ScrapeError>(), Some(ScrapeError { kind: ErrorKind::Status(reqwest::StatusCode::NOT_FOUND), .. })) => {
+                self.add_missing(url)?;
+                None
+            }
+            Err(error) => Err(error)?,
+        }
+    }
+
+    #[fehler::throws]
+    #[tracing::instrument(skip(self), fields(%url))]
+    pub(crate) fn post(&self, url: &Url, data: &serde_json::Value) -> Page {
+        if let Some(page) = self.get_from_cache(url, Method::Post, Some(data))? {
+            page
         } else {
-            let response = self.post_to_server(url, data)?;
-            self.add_to_cache(url, Method::Post, Some(data), &response)?;
-            response
+            let mut page = self.post_to_server(url, data)?;
+            page.cache_id = Some(self.add_to_cache(url, Method::Post, Some(data), &page)?);
+            page
         }
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url, data=%data.dbg()))]
-    fn get_from_cache(&self, url: &Url, method: Method, data: Option<&serde_json::Value>) -> Option<String> {
+    fn get_from_cache(&self, url: &Url, method: Method, data: Option<&serde_json::Value>) -> Option<Page> {
         let result = self
             .cache
             .query_row(
                 "
-                    select retrieved, response
+                    select id, retrieved, response, final_url
                     from pages
                     where url = :url and method = :method and data is :data
                 ",
                 named_params!(":url": url, ":method": method, ":data": data),
                 |row| {
                     Ok((
+                        row.get::<_, i64>("id")?,
                         row.get::<_, DateTime<Utc>>("retrieved")?,
                         row.get::<_, String>("response")?,
+                        row.get::<_, Option<Url>>("final_url")?,
                     ))
                 },
             )
             .optional()?;
 
-        if let Some((retrieved, response)) = result {
+        if let Some((_, retrieved, ..)) = result.as_ref().filter(|(_, retrieved, ..)| self.expired(*retrieved)) {
+            tracing::info!(%retrieved, "cache expired");
+            None
+        } else if let Some((id, retrieved, body, final_url)) = result {
             tracing::info!(%retrieved, "cache hit");
-            Some(response)
+            Some(Page { url: final_url.unwrap_or_else(|| url.clone()), body, cache_id: Some(id) })
         } else {
             tracing::info!("cache miss");
             None
@@ -142,36 +373,97 @@ impl Client {
         self.last_request.set(Instant::now());
     }
 
+    #[fehler::throws]
+    fn add_missing(&self, url: &Url) {
+        self.cache.execute(
+            "insert or replace into missing (url, retrieved) values (:url, :retrieved)",
+            named_params!(":url": url, ":retrieved": Utc::now()),
+        )?;
+    }
+
+    fn expired(&self, retrieved: DateTime<Utc>) -> bool {
+        self.max_age.is_some_and(|max_age| (Utc::now() - retrieved).to_std().is_ok_and(|age| age > max_age))
+    }
+
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url))]
-    fn get_from_server(&self, url: &Url) -> String {
+    fn get_from_server(&self, url: &Url) -> Page {
+        if self.offline {
+            eyre::bail!("offline, not fetching {url}");
+        }
         self.check_delay();
-        self.client.get(url.clone()).send()?.text()?
+        let response = self.client.get(url.clone()).send();
+        read_response(url, response)?
     }
 
     #[fehler::throws]
     #[tracing::instrument(skip(self), fields(%url, data=%data.dbg()))]
-    fn post_to_server(&self, url: &Url, data: &serde_json::Value) -> String {
+    fn post_to_server(&self, url: &Url, data: &serde_json::Value) -> Page {
+        if self.offline {
+            eyre::bail!("offline, not posting to {url}");
+        }
         self.check_delay();
-        self.client.post(url.clone()).json(data).send()?.text()?
+        let response = self.client.post(url.clone()).json(data).send();
+        read_response(url, response)?
     }
 
     #[fehler::throws]
-    #[tracing::instrument(skip(self, response), fields(%url, data=%data.dbg(), response_len=response.len()))]
-    fn add_to_cache(&self, url: &Url, method: Method, data: Option<&serde_json::Value>, response: &str) {
+    #[tracing::instrument(skip(self, page), fields(%url, data=%data.dbg(), final_url=%page.url, response_len=page.body.len()))]
+    fn add_to_cache(&self, url: &Url, method: Method, data: Option<&serde_json::Value>, page: &Page) -> i64 {
         self.cache.execute(
             "
-                insert
-                into pages (url, method, data, retrieved, response)
-                values (:url, :method, :data, :retrieved, :response)
+                insert or replace
+                into pages (url, method, data, retrieved, response, final_url)
+                values (:url, :method, :data, :retrieved, :response, :final_url)
             ",
             named_params! {
                 ":url": url,
                 ":method": method,
                 ":data": data,
                 ":retrieved": Utc::now(),
-                ":response": &response,
+                ":response": &page.body,
+                ":final_url": &page.url,
             },
         )?;
+        self.cache.last_insert_rowid()
+    }
+}
+
+/// Turns a server response into a page, rejecting failed requests so they are not cached
+#[fehler::throws]
+fn read_response(url: &Url, response: reqwest::Result<reqwest::blocking::Response>) -> Page {
+    let response = response.map_err(|e| ScrapeError::new(ErrorKind::Network(e), url.clone()))?;
+    let final_url = response.url().clone();
+    let status = response.status();
+    let body = response.text().map_err(|e| ScrapeError::new(ErrorKind::Network(e), final_url.clone()))?;
+    let page = Page { url: final_url, body, cache_id: None };
+    if !status.is_success() {
+        fehler::throw!(page.error(ErrorKind::Status(status)));
     }
+    page
 }
+
+/// The name of the struct a json document is parsed into, without module paths, to group drift by
+fn endpoint<T>() -> String {
+    let name = std::any::type_name::<T>();
+    let mut short = String::new();
+    // Start of the current path segment in `short`, dropped when it turns out to be a module
+    let mut segment = 0;
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("::") {
+            short.truncate(segment);
+            rest = after;
+            continue;
+        }
+        short.push(c);
+        if !(c.is_alphanumeric() || c == '_') {
+            segment = short.len();
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    short
+}
+
+#[cfg(test)]
+mod tests;
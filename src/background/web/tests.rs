@@ -0,0 +1,70 @@
+use std::time::Duration;
+use url::Url;
+use opt::data::{User, UserId};
+
+use crate::background::{crawl::{Crawl, CrawlConfig, Strategy}, Cursor, Priority, Request};
+use super::Client;
+
+#[test]
+fn expired_pages_are_fetched_again() {
+    let url = Url::parse("https://signed-artist.bandcamp.com/album/signed-debut").unwrap();
+    let client = Client::offline().unwrap().with_max_age(Some(Duration::ZERO));
+    client.insert(&url, None, include_str!("../scrape/testdata/album-more-thumbs.html")).unwrap();
+
+    assert!(client.get_page(&url).unwrap_err().to_string().starts_with("offline"));
+}
+
+#[test]
+fn missing_pages_are_not_fetched_again_until_expired() {
+    let url = Url::parse("https://example-artist.bandcamp.com/artists").unwrap();
+    let client = Client::offline().unwrap();
+    client.add_missing(&url).unwrap();
+    assert!(client.get_page_if_found(&url).unwrap().is_none());
+
+    let client = client.with_max_age(Some(Duration::ZERO));
+    assert!(client.get_page_if_found(&url).unwrap_err().to_string().starts_with("offline"));
+}
+
+#[test]
+fn saved_requests_are_restored_in_queued_order() {
+    let client = Client::offline().unwrap();
+    let album = |url: &str| Request::Album { url: url.to_owned() };
+    client.save_queued("a", Priority::Derived, &album("a")).unwrap();
+    client.save_queued("b", Priority::Derived, &album("b")).unwrap();
+    client.save_queued("c", Priority::Seed, &album("c")).unwrap();
+    client.save_queued("a", Priority::User, &album("a")).unwrap();
+    client.remove_queued("c").unwrap();
+
+    let queued = client.queued().unwrap();
+    let queued = queued.iter().map(|(key, priority, request)| (key.as_str(), *priority, request.url().unwrap())).collect::<Vec<_>>();
+    assert_eq!(queued, [("b", Priority::Derived, "b".to_owned()), ("a", Priority::User, "a".to_owned())]);
+}
+
+#[test]
+fn saved_cursors_are_replaced_and_removed_by_entity() {
+    let client = Client::offline().unwrap();
+    let user = User { id: UserId(1), url: "https://bandcamp.com/fan".to_owned() };
+    let cursor = |token: &str| Cursor::Collection { user: user.clone(), token: token.to_owned(), following: None };
+    client.save_partial(&cursor("1")).unwrap();
+    client.save_partial(&cursor("2")).unwrap();
+    assert_eq!(client.partials().unwrap(), [cursor("2")]);
+
+    client.remove_partial(&user.url).unwrap();
+    assert_eq!(client.partials().unwrap(), []);
+}
+
+#[test]
+fn saved_crawl_replaces_the_previous_one() {
+    let client = Client::offline().unwrap();
+    let config = CrawlConfig { strategy: Strategy::BreadthFirst, max_depth: 2, max_requests: 10, max_nodes: 10 };
+    assert!(client.crawl(config.clone()).unwrap().is_none());
+
+    let mut crawl = Crawl::new(config.clone());
+    client.save_crawl(&crawl).unwrap();
+    crawl.seed(&Request::User { url: "https://bandcamp.com/fan".to_owned() }, "https://bandcamp.com/fan");
+    client.save_crawl(&crawl).unwrap();
+
+    let mut restored = client.crawl(config).unwrap().unwrap();
+    assert_eq!(restored.next().and_then(|request| request.url()).as_deref(), Some("https://bandcamp.com/fan"));
+    assert!(restored.next().is_none());
+}
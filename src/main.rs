@@ -1,6 +1,6 @@
 use eyre::Error;
 use ggez::{event::EventHandler, input::{mouse::MouseButton, keyboard::{KeyInput, KeyMods, KeyCode}}, Context, ContextBuilder, GameResult, GameError, conf::WindowMode};
-use std::time::{Duration, Instant};
+use std::{collections::VecDeque, time::{Duration, Instant}};
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
 use crossbeam::channel::{Sender, Receiver, TryRecvError};
@@ -8,10 +8,10 @@ use clap::Parser;
 
 use opt::{
     phys::{Distance, Position, Velocity},
-    data::{Album, User, Data, EntityData},
+    data::{Data, EntityData, ScrapeState},
     sim,
 };
-use crate::ui::Ui;
+use crate::ui::{Activity, Ui};
 
 mod ui;
 mod background;
@@ -19,6 +19,8 @@ mod fps;
 
 const SIM_FREQ: u64 = 20;
 const SIM_TIME: Duration = Duration::from_millis(1000 / SIM_FREQ);
+/// Most responses merged at once, so a full channel can't hold up a frame merging more than its budget allows
+const MAX_BATCH: usize = 256;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -29,8 +31,61 @@ struct Args {
     albums: Vec<String>,
     #[arg(long("artist"), value_name("url"))]
     artists: Vec<String>,
+    #[arg(long("label"), value_name("url"))]
+    labels: Vec<String>,
+    #[arg(long("tag"), value_name("tag"))]
+    tags: Vec<String>,
+    /// Search Bandcamp, results are listed in the window to pick from with the number keys
+    #[arg(long("search"), value_name("query"))]
+    searches: Vec<String>,
+    #[arg(long, value_name("kind"), value_enum, default_value_t)]
+    search_kind: background::SearchKind,
     #[arg(long, value_names(["albums", "users"]), num_args(2))]
     random: Vec<u64>,
+    /// Maximum number of artists per label whose discographies are scraped
+    #[arg(long, value_name("count"), default_value_t = 50)]
+    roster_limit: usize,
+    /// Number of top releases scraped per tag
+    #[arg(long, value_name("count"), default_value_t = 20)]
+    tag_limit: usize,
+    /// Fail scraping pages whose json has fields we don't know about
+    #[arg(long)]
+    strict_schema: bool,
+    /// Fans scraped per album before it is left partially scraped
+    #[arg(long, value_name("count"), default_value_t = 1000)]
+    max_fans: usize,
+    /// Collection items scraped per fan before they are left partially scraped
+    #[arg(long, value_name("count"), default_value_t = 1000)]
+    max_collection_items: usize,
+    /// Pages fetched per request before it is left partially scraped
+    #[arg(long, value_name("count"), default_value_t = 50)]
+    max_pages: usize,
+    /// Time spent per request before it is left partially scraped
+    #[arg(long, value_name("seconds"), default_value_t = 300)]
+    max_time: u64,
+    /// Keep expanding the graph from the seeds on its own, picking what to scrape next with this strategy
+    ///
+    /// The crawl is saved next to the web cache and carries on from where the last run left it.
+    #[arg(long, value_name("strategy"), value_enum)]
+    crawl: Option<background::Strategy>,
+    /// Links away from the nearest seed the crawl may go
+    #[arg(long, value_name("links"), default_value_t = 2)]
+    crawl_depth: usize,
+    /// Requests the crawl may make on its own
+    #[arg(long, value_name("count"), default_value_t = 1000)]
+    crawl_requests: usize,
+    /// Entities the crawl may discover
+    #[arg(long, value_name("count"), default_value_t = 10000)]
+    crawl_nodes: usize,
+    /// Responses the background thread may get ahead of the window by
+    #[arg(long, value_name("count"), default_value_t = 1024)]
+    channel_capacity: usize,
+    /// Time per frame spent adding scraped responses to the graph
+    #[arg(long, value_name("milliseconds"), default_value_t = 8)]
+    response_budget: u64,
+    /// Re-scrape entities, ignoring cached pages, once they were scraped longer ago than this
+    #[arg(long, value_name("minutes"))]
+    refresh_after: Option<u64>,
 }
 
 fn main() -> eyre::Result<()> {
@@ -60,18 +115,47 @@ fn main() -> eyre::Result<()> {
     // Create an instance of your event handler.
     // Usually, you should provide it with the Context object
     // so it can load resources like images during setup.
-    let mut ui = App::new(&mut ctx)?;
+    let mut ui = App::new(&mut ctx, background::Config {
+        roster_limit: args.roster_limit,
+        tag_limit: args.tag_limit,
+        strict_schema: args.strict_schema,
+        budget: background::Budget {
+            max_fans: args.max_fans,
+            max_collection_items: args.max_collection_items,
+            max_pages: args.max_pages,
+            max_time: Duration::from_secs(args.max_time),
+        },
+        crawl: args.crawl.map(|strategy| background::CrawlConfig {
+            strategy,
+            max_depth: args.crawl_depth,
+            max_requests: args.crawl_requests,
+            max_nodes: args.crawl_nodes,
+        }),
+        refresh_after: args.refresh_after.map(|minutes| Duration::from_secs(minutes * 60)),
+    }, args.channel_capacity, Duration::from_millis(args.response_budget))?;
 
     for url in args.albums {
-        ui.to_scrape_tx.send(background::Request::Album { url })?;
+        ui.to_scrape_tx.send((background::Request::Album { url }, background::Priority::Seed))?;
     }
 
     for username in args.users {
-        ui.to_scrape_tx.send(background::Request::User { url: format!("https://bandcamp.com/{username}") })?;
+        ui.to_scrape_tx.send((background::Request::User { url: format!("https://bandcamp.com/{username}") }, background::Priority::Seed))?;
     }
 
     for url in args.artists {
-        ui.to_scrape_tx.send(background::Request::Artist { url })?;
+        ui.to_scrape_tx.send((background::Request::Artist { url }, background::Priority::Seed))?;
+    }
+
+    for url in args.labels {
+        ui.to_scrape_tx.send((background::Request::Label { url }, background::Priority::Seed))?;
+    }
+
+    for tag in args.tags {
+        ui.to_scrape_tx.send((background::Request::Tag { tag }, background::Priority::Seed))?;
+    }
+
+    for query in args.searches {
+        ui.to_scrape_tx.send((background::Request::Search { query, kind: args.search_kind }, background::Priority::Seed))?;
     }
 
     if let [albums, users] = args.random[..] {
@@ -90,19 +174,25 @@ struct App {
     tps: fps::Counter<2>,
     fps: fps::Counter<120>,
     pause_sim: bool,
+    /// Time per frame spent applying responses from the background thread
+    response_budget: Duration,
+    /// Merged responses the last frame's budget ran out before applying
+    pending: VecDeque<background::Response>,
+    /// Whether the request being handled is re-scraping a stale entity, so the relationships it finds are new ones
+    refreshing: bool,
     // Order matters, sender and receiver must be dropped before background thread to tell it to shutdown
-    to_scrape_tx: Sender<background::Request>,
+    to_scrape_tx: Sender<(background::Request, background::Priority)>,
     scraped_rx: Receiver<background::Response>,
     _background: background::Thread,
 }
 
 impl App {
     #[fehler::throws]
-    pub fn new(ctx: &mut Context) -> Self {
-        let (scraped_tx, scraped_rx) = crossbeam::channel::bounded(1);
+    pub fn new(ctx: &mut Context, config: background::Config, channel_capacity: usize, response_budget: Duration) -> Self {
+        let (scraped_tx, scraped_rx) = crossbeam::channel::bounded(channel_capacity);
         let (to_scrape_tx, to_scrape_rx) = crossbeam::channel::unbounded();
 
-        let _background = background::Thread::spawn(to_scrape_rx, scraped_tx)?;
+        let _background = background::Thread::spawn(config, to_scrape_rx, scraped_tx)?;
 
         Self {
             data: Data::default(),
@@ -112,6 +202,9 @@ impl App {
             fps: fps::Counter::new(60.0),
             last_mouse_position: Position::new(0.0, 0.0),
             pause_sim: false,
+            response_budget,
+            pending: VecDeque::new(),
+            refreshing: false,
             to_scrape_tx,
             scraped_rx,
             _background,
@@ -119,6 +212,152 @@ impl App {
     }
 }
 
+impl App {
+    /// Sends a request to the background thread, which only goes away once the window is closing
+    fn request(&self, request: background::Request, priority: background::Priority) {
+        if let Err(error) = self.to_scrape_tx.send((request, priority)) {
+            tracing::error!(?error, "background thread stopped");
+        }
+    }
+
+    /// Requests scraping an entity ahead of everything else queued, or continuing it if it was left partially scraped
+    fn scrape(&mut self, entity: &EntityData) {
+        if let Some(ScrapeState::Partial(cursor) | ScrapeState::Failed { cursor: Some(cursor), .. }) = self.data.state(entity.url()) {
+            let cursor = cursor.clone();
+            self.request(background::Request::Continue { cursor }, background::Priority::User);
+            return;
+        }
+        self.request(background::Request::scrape(entity), background::Priority::User);
+    }
+
+    /// Stops scraping the entities under the mouse, whatever was scraped so far is kept
+    fn cancel_under_mouse(&mut self) {
+        for entity in (&self.data.entities).into_iter().filter(|entity| entity.is_under_mouse) {
+            let url = entity.data.url().to_owned();
+            self.request(background::Request::Cancel { url }, background::Priority::User);
+        }
+    }
+
+    /// Adds relationships found by the request being handled, flagging new ones if it is a re-scrape
+    fn link(&mut self, f: impl FnOnce(&mut Data)) {
+        if self.refreshing {
+            self.data.refreshed(f);
+        } else {
+            f(&mut self.data);
+        }
+    }
+
+    /// Applies a response from the background thread to the graph and ui
+    fn handle_response(&mut self, response: background::Response) {
+        match response {
+            background::Response::Fans(album, users) => self.link(|data| {
+                for user in &users {
+                    data.add_relationship(&album, user);
+                }
+            }),
+            background::Response::Collection(user, purchases) => self.link(|data| {
+                for purchase in &purchases {
+                    data.add_purchase(purchase, &user);
+                }
+            }),
+            background::Response::Follows(user, artists) => self.link(|data| {
+                for artist in &artists {
+                    data.add_follow(&user, artist);
+                }
+            }),
+            background::Response::Credit(album, artist) => self.link(|data| data.add_release(&album, &artist)),
+            background::Response::Recommended(album, recommended) => self.link(|data| {
+                for other in &recommended {
+                    data.add_recommendation(&album, other);
+                }
+            }),
+            background::Response::Roster(label, artists) => self.link(|data| {
+                for artist in &artists {
+                    data.add_roster(artist, &label);
+                }
+            }),
+            background::Response::Release(url) => {
+                self.request(background::Request::Album { url }, background::Priority::Derived);
+            }
+            background::Response::Discography(url) => {
+                self.request(background::Request::Artist { url }, background::Priority::Derived);
+            }
+            background::Response::Scraped(entity, at) => self.data.set_scraped(entity, at),
+            background::Response::Partial(entity, cursor, at) => self.data.set_partial(entity, cursor, at),
+            background::Response::SearchResults(results) => self.ui.search_results.extend(results),
+            background::Response::Queued { url } => self.data.queued(&url),
+            background::Response::Unqueued { url } => self.data.unqueued(&url),
+            background::Response::Started { url, queued, refresh } => {
+                self.stopped();
+                self.refreshing = refresh;
+                if let Some(url) = &url {
+                    self.data.started(url);
+                }
+                self.ui.activity = Some(Activity { url, queued, fetched: 0, expected: None });
+            }
+            background::Response::Progress { fetched, expected } => {
+                if let Some(activity) = &mut self.ui.activity {
+                    activity.fetched = fetched;
+                    activity.expected = expected;
+                }
+            }
+            background::Response::Failed { url: Some(url), error, cursor } => self.data.failed(&url, error, cursor),
+            background::Response::Failed { url: None, error, .. } => self.ui.last_error = Some(error),
+            background::Response::Idle => {
+                self.stopped();
+                self.refreshing = false;
+                self.ui.activity = None;
+            }
+        }
+    }
+
+    /// Tells the graph the previous request is over, in case it was cancelled without any results
+    fn stopped(&mut self) {
+        if let Some(url) = self.ui.activity.as_ref().and_then(|activity| activity.url.as_deref()) {
+            self.data.stopped(url);
+        }
+    }
+
+    /// Applies responses until the channel is empty or this frame's budget is spent, merging each batch first so
+    /// relationships are added per entity
+    ///
+    /// The budget is checked before every response, whatever is left of a merged batch is applied next frame.
+    fn drain_responses(&mut self) {
+        let started = Instant::now();
+        while started.elapsed() < self.response_budget {
+            if let Some(response) = self.pending.pop_front() {
+                self.handle_response(response);
+                continue;
+            }
+            let mut batch = Vec::new();
+            while batch.len() < MAX_BATCH {
+                match self.scraped_rx.try_recv() {
+                    Ok(response) => batch.push(response),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.ui.activity = None;
+                        self.ui.last_error = Some("background thread stopped, nothing more will be scraped".to_owned());
+                        break;
+                    }
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+            self.pending.extend(background::merge(batch));
+        }
+    }
+
+    /// Adds the picked search result to the graph and scrapes it
+    fn pick_search_result(&mut self, index: usize) {
+        if index < self.ui.search_results.len() {
+            let result = self.ui.search_results.remove(index);
+            self.data.add_node(&result.entity);
+            self.scrape(&result.entity);
+        }
+    }
+}
+
 impl EventHandler for App {
     fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeat: bool) -> GameResult<()> {
         match input.keycode {
@@ -137,6 +376,15 @@ impl EventHandler for App {
             Some(KeyCode::N) => {
                 self.ui.enable_nodes ^= true;
             }
+            Some(KeyCode::C) if input.mods.contains(KeyMods::SHIFT) => {
+                self.request(background::Request::CancelAll, background::Priority::User);
+            }
+            Some(KeyCode::C) => {
+                self.cancel_under_mouse();
+            }
+            Some(key @ (KeyCode::Key1 | KeyCode::Key2 | KeyCode::Key3 | KeyCode::Key4 | KeyCode::Key5 | KeyCode::Key6 | KeyCode::Key7 | KeyCode::Key8 | KeyCode::Key9)) => {
+                self.pick_search_result(key as usize - KeyCode::Key1 as usize);
+            }
             _ => {}
         }
         Ok(())
@@ -149,14 +397,8 @@ impl EventHandler for App {
 
     fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult<()> {
         if let Some(entity) = self.ui.mouse_up(&mut self.data, ctx, button, Position::new(x, y)) {
-            match &*entity.data {
-                EntityData::Album(Album { url, .. }) => {
-                    self.to_scrape_tx.send(background::Request::Album { url: url.clone() }).unwrap();
-                }
-                EntityData::User(User { url, .. }) => {
-                    self.to_scrape_tx.send(background::Request::User { url: url.clone() }).unwrap();
-                }
-            }
+            let entity = entity.data.clone();
+            self.scrape(&entity);
         }
         Ok(())
     }
@@ -184,6 +426,7 @@ impl EventHandler for App {
 
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         self.ui.update(&mut self.data, ctx);
+        self.drain_responses();
         if ctx.time.check_update_time(SIM_FREQ as u32) {
             if self.pause_sim {
                 self.tps.reset_start();
@@ -192,37 +435,6 @@ impl EventHandler for App {
                     sim::update(&mut self.data, SIM_TIME);
                 });
             }
-            match self.scraped_rx.try_recv() {
-                Ok(response) => match response {
-                    background::Response::Fans(album, users) => {
-                        for user in users {
-                            self.data.add_relationship(&album, &user);
-                        }
-                    }
-                    background::Response::Collection(user, albums) => {
-                        for album in albums {
-                            self.data.add_relationship(&album, &user);
-                        }
-                    }
-                    background::Response::Release(url) => {
-                        self.to_scrape_tx.send(background::Request::Album { url }).unwrap();
-                    }
-                    background::Response::Album(Album { id, .. }) => {
-                        if let Some(&id) = self.data.albums.get(&id) {
-                            self.data.entities[id].is_scraped = true;
-                        }
-                    }
-                    background::Response::User(User { id, .. }) => {
-                        if let Some(&id) = self.data.users.get(&id) {
-                            self.data.entities[id].is_scraped = true;
-                        }
-                    }
-                }
-                Err(TryRecvError::Empty) => {}
-                Err(TryRecvError::Disconnected) => {
-                    panic!("background thread ded?");
-                }
-            }
             self.last_update = Instant::now();
         }
 
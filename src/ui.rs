@@ -8,10 +8,20 @@ use itertools::Itertools;
 
 use opt::{
     phys::{Distance, Position, Velocity, Float},
-    data::{Data, Album, User, Entity, EntityData, Drag},
+    data::{Data, Album, AlbumId, AlbumInfo, User, Artist, Label, Entity, EntityData, Drag, RelationshipKind, ItemKind, ScrapeState},
 };
+use crate::background::SearchResult;
+
+/// Only as many search results as there are number keys can be picked
+const SEARCH_RESULTS_SHOWN: usize = 9;
 
 const LIGHT_RED: Color = Color::new(1.0, 0.0, 0.0, 0.2);
+const LIGHT_GREEN: Color = Color::new(0.0, 0.8, 0.0, 0.3);
+const LIGHT_BLUE: Color = Color::new(0.0, 0.0, 1.0, 0.3);
+const LIGHT_PURPLE: Color = Color::new(0.6, 0.0, 0.8, 0.3);
+const LIGHT_ORANGE: Color = Color::new(1.0, 0.5, 0.0, 0.3);
+/// Relationships found by re-scraping stand out from everything else
+const CHANGED: Color = Color::new(1.0, 0.85, 0.0, 0.9);
 
 #[derive(Debug)]
 struct Camera {
@@ -22,21 +32,65 @@ struct Camera {
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone, Copy)]
 enum EntityTag {
     Album,
-    User
+    Track,
+    User,
+    Artist,
+    Label,
+}
+
+/// How far along scraping an entity is, without what only the hover text shows
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone, Copy)]
+enum StateTag {
+    Unscraped,
+    Queued,
+    InProgress,
+    Partial,
+    Done,
+    Failed,
+}
+
+impl From<&ScrapeState> for StateTag {
+    fn from(state: &ScrapeState) -> Self {
+        match state {
+            ScrapeState::Unscraped => StateTag::Unscraped,
+            ScrapeState::Queued => StateTag::Queued,
+            ScrapeState::InProgress => StateTag::InProgress,
+            ScrapeState::Partial(_) => StateTag::Partial,
+            ScrapeState::Done => StateTag::Done,
+            ScrapeState::Failed { .. } => StateTag::Failed,
+        }
+    }
 }
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone, Copy)]
 struct MeshKey {
     tag: EntityTag,
-    is_scraped: bool,
+    state: StateTag,
     is_under_mouse: bool,
 }
 
+/// What the background thread is scraping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    /// The entity being scraped, `None` for tags and searches
+    pub url: Option<String>,
+    /// Requests still waiting behind this one
+    pub queued: usize,
+    pub fetched: usize,
+    pub expected: Option<usize>,
+}
+
 #[derive(Debug)]
 pub struct Ui {
     camera: Camera,
     pub enable_lines: bool,
     pub enable_nodes: bool,
+    /// Candidates from searches that haven't been picked yet
+    pub search_results: Vec<SearchResult>,
+    /// `None` while the background thread is idle
+    pub activity: Option<Activity>,
+    /// The latest failure not tied to an entity, e.g. a search or the background thread itself
+    pub last_error: Option<String>,
     meshes: BTreeMap<MeshKey, Mesh>,
     foreground: Color,
     background: Color,
@@ -55,6 +109,14 @@ impl Ui {
         let highlight = Color::new(0.2, 1.0, 0.2, 1.0);
         let scraped = Color::new(0.2, 0.2, 1.0, 1.0);
         let both = Color::new(0.2, 1.0, 1.0, 1.0);
+        let states = [
+            (StateTag::Unscraped, fg),
+            (StateTag::Queued, Color::new(0.5, 0.5, 0.5, 1.0)),
+            (StateTag::InProgress, Color::new(1.0, 0.6, 0.0, 1.0)),
+            (StateTag::Partial, Color::new(0.7, 0.3, 1.0, 1.0)),
+            (StateTag::Done, scraped),
+            (StateTag::Failed, Color::new(1.0, 0.2, 0.2, 1.0)),
+        ];
 
         let meshes = BTreeMap::from_iter(
             [
@@ -66,16 +128,28 @@ impl Ui {
                     EntityTag::Album,
                     |ctx, color| Mesh::new_circle(ctx, DrawMode::fill(), [0.0, 0.0], 5.0, 0.1, color).unwrap(),
                 ),
+                (
+                    EntityTag::Track,
+                    |ctx, color| Mesh::new_circle(ctx, DrawMode::stroke(2.0), [0.0, 0.0], 3.0, 0.1, color).unwrap(),
+                ),
+                (
+                    EntityTag::Artist,
+                    |ctx, color| Mesh::new_polygon(ctx, DrawMode::fill(), &[[0.0, -7.0], [6.0, 4.0], [-6.0, 4.0]], color).unwrap(),
+                ),
+                (
+                    EntityTag::Label,
+                    |ctx, color| Mesh::new_polygon(ctx, DrawMode::fill(), &[[0.0, -8.0], [8.0, 0.0], [0.0, 8.0], [-8.0, 0.0]], color).unwrap(),
+                ),
             ]
                 .into_iter()
-                .cartesian_product([
-                    (false, false, fg),
-                    (false, true, highlight),
-                    (true, false, scraped),
-                    (true, true, both),
-                ])
-                .map(|((tag, make), (is_scraped, is_under_mouse, color))| {
-                    (MeshKey { tag, is_scraped, is_under_mouse }, make(ctx, color))
+                .cartesian_product(states.into_iter().cartesian_product([false, true]))
+                .map(|((tag, make), ((state, color), is_under_mouse))| {
+                    let color = match (is_under_mouse, state) {
+                        (false, _) => color,
+                        (true, StateTag::Done) => both,
+                        (true, _) => highlight,
+                    };
+                    (MeshKey { tag, state, is_under_mouse }, make(ctx, color))
                 }));
 
         Self { 
@@ -85,6 +159,9 @@ impl Ui {
             },
             enable_lines: true,
             enable_nodes: true,
+            search_results: Vec::new(),
+            activity: None,
+            last_error: None,
             meshes,
             foreground: fg,
             background: bg,
@@ -95,10 +172,13 @@ impl Ui {
 
     fn mesh_for(&self, entity: &Entity) -> &Mesh {
         let tag = match &*entity.data {
-            EntityData::Album(_) => EntityTag::Album,
+            EntityData::Album(Album { id: AlbumId(ItemKind::Album, _), .. }) => EntityTag::Album,
+            EntityData::Album(Album { id: AlbumId(ItemKind::Track, _), .. }) => EntityTag::Track,
             EntityData::User(_) => EntityTag::User,
+            EntityData::Artist(_) => EntityTag::Artist,
+            EntityData::Label(_) => EntityTag::Label,
         };
-        &self.meshes[&MeshKey { tag, is_scraped: entity.is_scraped, is_under_mouse: entity.is_under_mouse }]
+        &self.meshes[&MeshKey { tag, state: StateTag::from(&entity.state), is_under_mouse: entity.is_under_mouse }]
     }
 
     fn draw_entities(&self, data: &Data, canvas: &mut Canvas, delta: Duration, (tl, br): (Position, Position)) -> usize {
@@ -117,13 +197,21 @@ impl Ui {
         let mut mesh = MeshBuilder::new();
         let mut count = 0;
         for rel in &data.relationships {
-            let entity1 = &data.entities[rel.album];
-            let entity2 = &data.entities[rel.user];
+            let entity1 = &data.entities[rel.from];
+            let entity2 = &data.entities[rel.to];
             let pos1 = entity1.position + entity1.velocity * delta;
             let pos2 = entity2.position + entity2.velocity * delta;
             let dist = pos1 - pos2;
             if dist.chebyshev().abs() > 1.0 {
-                mesh.line(&[pos1, pos2], 0.5, LIGHT_RED).unwrap();
+                let color = match rel.kind {
+                    RelationshipKind::Purchase => LIGHT_RED,
+                    RelationshipKind::Release => LIGHT_GREEN,
+                    RelationshipKind::Roster => LIGHT_BLUE,
+                    RelationshipKind::Recommendation => LIGHT_PURPLE,
+                    RelationshipKind::Follow => LIGHT_ORANGE,
+                };
+                let (color, width) = if data.changed.contains_key(rel) { (CHANGED, 1.5) } else { (color, 0.5) };
+                mesh.line(&[pos1, pos2], width, color).unwrap();
                 count += 1;
             }
         }
@@ -134,36 +222,109 @@ impl Ui {
         count
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_status_bar(&self, data: &Data, ctx: &mut Context, canvas: &mut Canvas, tps: f64, sim_duration: Duration, fps: f64, frame_duration: Duration, nodes: usize, _lines: usize) {
-        let albums = data.albums.len();
+        let tracks = data.albums.keys().filter(|AlbumId(kind, _)| *kind == ItemKind::Track).count();
+        let albums = data.albums.len() - tracks;
         let users = data.users.len();
+        let artists = data.artists.len();
+        let labels = data.labels.len();
 
         let text = Text::new(format!(indoc::indoc!("
             tps: {:.2} ({:.2?})
             fps: {:.2} ({:.2?})
             drawn: {}/{}
-        "), tps, sim_duration, fps, frame_duration, nodes, (albums + users)));
+        "), tps, sim_duration, fps, frame_duration, nodes, (albums + tracks + users + artists + labels)));
 
         let width = text.measure(ctx).unwrap().x;
         canvas.draw(&text, DrawParam::from([self.width - width as f32, 0.0]).color(self.foreground));
 
         let links = data.relationships.len();
+        let state_count = |state| (&data.entities).into_iter().filter(|entity| StateTag::from(&entity.state) == state).count();
 
         let mut text = Text::new(format!(indoc::indoc!("
             albums: {}
+            tracks: {}
             users: {}
+            artists: {}
+            labels: {}
             links: {}
-        "), albums, users, links));
+            changed: {}
+            partial: {}
+            failed: {}
+        "), albums, tracks, users, artists, labels, links, data.changed.len(), state_count(StateTag::Partial), state_count(StateTag::Failed)));
+
+        match &self.activity {
+            Some(Activity { url, queued, fetched, expected }) => {
+                text.add(format!("scraping: {}", url.as_deref().unwrap_or("tag or search")));
+                match (fetched, expected) {
+                    (0, _) => {}
+                    (fetched, Some(expected)) => {
+                        text.add(format!(" ({fetched}/{expected})"));
+                    }
+                    (fetched, None) => {
+                        text.add(format!(" ({fetched}/?)"));
+                    }
+                }
+                text.add(format!("\nqueued: {queued}"));
+            }
+            None => {
+                text.add("idle");
+            }
+        }
+        if let Some(error) = &self.last_error {
+            text.add(format!("\nerror: {error}"));
+        }
 
         for entity in &data.entities {
             if entity.is_under_mouse {
                 match &*entity.data {
-                    EntityData::Album(Album { url, .. }) => {
-                        text.add(format!("\nalbum: {url}"));
+                    EntityData::Album(Album { id: id @ AlbumId(kind, _), url }) => {
+                        let kind = match kind {
+                            ItemKind::Album => "album",
+                            ItemKind::Track => "track",
+                        };
+                        text.add(format!("\n{kind}: {url}"));
+                        if let Some(AlbumInfo { title, artist, genre_id, featured_track }) = data.album_info.get(id) {
+                            text.add(format!("\n  {title} by {artist}"));
+                            if let Some(genre) = genre_id {
+                                text.add(format!(" (genre {genre})"));
+                            }
+                            if let Some((_, track)) = featured_track {
+                                text.add(format!("\n  featuring: {track}"));
+                            }
+                        }
                     }
                     EntityData::User(User { url, .. }) => {
                         text.add(format!("\nuser: {url}"));
                     }
+                    EntityData::Artist(Artist { url, .. }) => {
+                        text.add(format!("\nartist: {url}"));
+                    }
+                    EntityData::Label(Label { url, .. }) => {
+                        text.add(format!("\nlabel: {url}"));
+                    }
+                }
+                match &entity.state {
+                    ScrapeState::Unscraped | ScrapeState::Done => {}
+                    ScrapeState::Queued => {
+                        text.add(" (queued)");
+                    }
+                    ScrapeState::InProgress => {
+                        text.add(" (scraping)");
+                    }
+                    ScrapeState::Partial(_) => {
+                        text.add(" (partial, click to continue)");
+                    }
+                    ScrapeState::Failed { error, cursor } => {
+                        text.add(format!("\n  failed: {error}"));
+                        if cursor.is_some() {
+                            text.add(", click to continue");
+                        }
+                    }
+                }
+                if let Some(at) = entity.scraped_at {
+                    text.add(format!("\n  scraped: {}", chrono::DateTime::<chrono::Utc>::from(at).format("%Y-%m-%d %H:%M UTC")));
                 }
             }
         }
@@ -177,14 +338,39 @@ impl Ui {
         canvas.draw(&text, DrawParam::from([0.0, self.height - height as f32]).color(self.foreground));
     }
 
+    fn draw_search_results(&self, ctx: &mut Context, canvas: &mut Canvas) {
+        if self.search_results.is_empty() {
+            return;
+        }
+        let mut text = Text::new("search results:");
+        for (i, SearchResult { name, entity }) in self.search_results.iter().take(SEARCH_RESULTS_SHOWN).enumerate() {
+            let (kind, url) = match entity {
+                EntityData::Album(Album { id: AlbumId(ItemKind::Album, _), url }) => ("album", url),
+                EntityData::Album(Album { id: AlbumId(ItemKind::Track, _), url }) => ("track", url),
+                EntityData::User(User { url, .. }) => ("user", url),
+                EntityData::Artist(Artist { url, .. }) => ("artist", url),
+                EntityData::Label(Label { url, .. }) => ("label", url),
+            };
+            text.add(format!("\n{}. {kind}: {name} ({url})", i + 1));
+        }
+        if self.search_results.len() > SEARCH_RESULTS_SHOWN {
+            text.add(format!("\n... {} more", self.search_results.len() - SEARCH_RESULTS_SHOWN));
+        }
+        // Leave room for the mouse position line below
+        let height = text.measure(ctx).unwrap().y + Text::new("0").measure(ctx).unwrap().y;
+        canvas.draw(&text, DrawParam::from([0.0, self.height - height]).color(self.foreground));
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn draw(&mut self, data: &Data, ctx: &mut Context, delta: Duration, tps: f64, sim_duration: Duration, fps: f64, frame_duration: Duration) {
         let mut canvas = Canvas::from_frame(ctx, self.background);
         canvas.set_projection(DrawParam::new().dest(self.camera.position).scale([self.camera.zoom, self.camera.zoom]).transform.to_bare_matrix());
         let (tl, br) = (self.offset_to_camera(Position::new(0.0, 0.0)), self.offset_to_camera(Position::new(self.width, self.height)));
-        let lines = self.enable_lines.then(|| self.draw_relationships(data, ctx, &mut canvas, delta)).unwrap_or_default();
-        let nodes = self.enable_nodes.then(|| self.draw_entities(data, &mut canvas, delta, (tl, br))).unwrap_or_default();
+        let lines = if self.enable_lines { self.draw_relationships(data, ctx, &mut canvas, delta) } else { 0 };
+        let nodes = if self.enable_nodes { self.draw_entities(data, &mut canvas, delta, (tl, br)) } else { 0 };
         canvas.set_projection(DrawParam::new().transform.to_bare_matrix());
         self.draw_status_bar(data, ctx, &mut canvas, tps, sim_duration, fps, frame_duration, nodes, lines);
+        self.draw_search_results(ctx, &mut canvas);
         canvas.finish(ctx).unwrap();
     }
 